@@ -1,4 +1,5 @@
-use core::f32::consts::{SQRT_2, TAU};
+use core::f32::consts::{FRAC_1_SQRT_2, PI, SQRT_2, TAU};
+use nih_plug::prelude::Enum;
 use std::cmp::Ordering;
 
 // Utility functions
@@ -13,148 +14,839 @@ pub fn get_max_float(values: &[f32]) -> f32 {
     max
 }
 
-// Uniform random between 0.3 and 0.8
-pub const DELAYS: [f32; 32] = [
-    0.7635944581685638,
-    0.5054930849771431,
-    0.6367330554100172,
-    0.5988550749564814,
-    0.5627433438493729,
-    0.5292359969882741,
-    0.39609197804744667,
-    0.44730718318451523,
-    0.6209089620338925,
-    0.3002814785332507,
-    0.691114361058875,
-    0.5725195228704965,
-    0.5018684398985229,
-    0.40848200614524044,
-    0.38804969100135855,
-    0.547990456049264,
-    0.5411968694743376,
-    0.5355559000178921,
-    0.7761144831398028,
-    0.5059573559952342,
-    0.418257481544177,
-    0.4566727793084992,
-    0.41703684491687243,
-    0.7100284150808744,
-    0.7510161891522189,
-    0.4399213022865711,
-    0.38414361694332666,
-    0.6607107641090827,
-    0.6706640816518474,
-    0.47339770016067245,
-    0.7967096565876597,
-    0.6578104668408047,
-];
+fn is_prime(value: usize) -> bool {
+    if value < 2 {
+        return false;
+    }
+
+    let mut divisor = 2;
+    while divisor * divisor <= value {
+        if value.is_multiple_of(divisor) {
+            return false;
+        }
+        divisor += 1;
+    }
+
+    true
+}
+
+// Smallest prime at or above a value
+fn next_prime(mut value: usize) -> usize {
+    while !is_prime(value) {
+        value += 1;
+    }
+    value
+}
+
+// Most delay lines in the tank
+pub const MAX_ORDER: usize = 64;
+
+// Range of the delay line lengths in seconds, at full size
+pub const MIN_DELAY: f32 = 0.3;
+pub const MAX_DELAY: f32 = 0.8;
+
+pub const DEFAULT_SEED: u32 = 1;
+
+/// How the delay line lengths are spread between MIN_DELAY and MAX_DELAY
+#[derive(Enum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DelayStrategy {
+    /// Distinct primes near a logarithmic spread, so no two lines share a period
+    #[default]
+    #[name = "Mutually Prime"]
+    MutuallyPrime,
+    /// Evenly spread on a log scale with a little jitter
+    Logarithmic,
+    /// Uniform random
+    Random,
+}
+
+/// Delay line lengths in samples for a seed. Sorted sets are stored in bit reversed order, so the
+/// lines used at every power of two order still span the whole range
+/// https://ccrma.stanford.edu/~jos/pasp/Choice_Delay_Line_Lengths.html
+pub fn generate_delays(seed: u32, strategy: DelayStrategy, sample_rate: f32) -> [f32; MAX_ORDER] {
+    // Scramble the seed, nearby seeds give nearby first values otherwise
+    let mut random = Random::new(seed.wrapping_mul(0x9E37_79B9));
+    let step = 1.0 / (MAX_ORDER - 1) as f32;
+
+    // Each length moves at random by up to half a step either way
+    let mut log_spread = |ii: usize| {
+        let position = (ii as f32 + random.next_bipolar() * 0.5) * step;
+        MIN_DELAY * (MAX_DELAY / MIN_DELAY).powf(position.clamp(0.0, 1.0)) * sample_rate
+    };
+
+    let mut sorted = [0.0; MAX_ORDER];
+    match strategy {
+        DelayStrategy::MutuallyPrime => {
+            let mut previous = 0;
+            for (ii, delay) in sorted.iter_mut().enumerate() {
+                let length = next_prime((log_spread(ii).round() as usize).max(previous + 1));
+                previous = length;
+                *delay = length as f32;
+            }
+        }
+        DelayStrategy::Logarithmic => {
+            for (ii, delay) in sorted.iter_mut().enumerate() {
+                *delay = log_spread(ii);
+            }
+        }
+        DelayStrategy::Random => {
+            return std::array::from_fn(|_| {
+                let position = random.next_bipolar() * 0.5 + 0.5;
+                (MIN_DELAY + (MAX_DELAY - MIN_DELAY) * position) * sample_rate
+            });
+        }
+    }
+
+    let bits = MAX_ORDER.trailing_zeros();
+    std::array::from_fn(|ii| sorted[ii.reverse_bits() >> (usize::BITS - bits)])
+}
+
+// Delay lines that run by default, out of the MAX_ORDER available
+pub const DEFAULT_ORDER: usize = 32;
 
 pub const DEFAULT_SAMPLE_RATE: usize = 44100;
+pub const DEFAULT_MAX_BLOCK_SIZE: usize = 512;
+
+pub const MAX_SIZE: f32 = 1.0;
+// Time a delay line takes by default to crossfade to a new length when Size changes, and the
+// longest it can be set to
+pub const SIZE_CROSSFADE: f32 = 0.05;
+pub const MAX_SIZE_CROSSFADE: f32 = 1.0;
+// Furthest the modulation moves a delay line, in seconds
+pub const MAX_MOD_DEPTH: f32 = 0.005;
+// Signs of the orthogonal routing, the same for every instance
+const JUNCTION_SEED: u32 = 1;
+// Lowest highpass in the tank in Hz, enough to drain DC
+const DC_BLOCKER_CUTOFF: f32 = 5.0;
+// Pitch shifter window of the shimmer in seconds
+const SHIMMER_WINDOW: f32 = 0.1;
+// Time to glide in and out of Freeze, in seconds
+const FREEZE_TIME: f32 = 0.05;
+
+// Longest reverse window in seconds, and the fades at either end of a window
+pub const MAX_REVERSE: f32 = 2.0;
+const REVERSE_FADE: f32 = 0.01;
+
+// Pivot of the output EQ tilt in Hz
+const TILT_FREQUENCY: f32 = 1000.0;
+
+// Longest pre-delay in seconds, long enough for tempo synced values at slow tempos
+pub const MAX_PREDELAY: f32 = 2.0;
+
+// Input diffuser lengths in seconds for each channel, after Dattorro's plate
+// https://ccrma.stanford.edu/~dattorro/EffectDesignPart1.pdf
+const DIFFUSER_DELAYS: [[f32; 4]; 2] = [
+    [0.00477, 0.0036, 0.01273, 0.00931],
+    [0.00513, 0.00337, 0.01187, 0.00983],
+];
+
+// Allpass gain at full diffusion
+const MAX_DIFFUSION: f32 = 0.75;
+
+// Meters per second
+const SPEED_OF_SOUND: f32 = 343.0;
+// Reflections rendered for each ear
+const EARLY_TAPS: usize = 24;
+// Highest number of wall bounces in a reflection
+const EARLY_ORDER: i32 = 3;
+// Latest reflection after the direct sound, in seconds
+const MAX_EARLY_DELAY: f32 = 0.25;
+// Distance between the listener's ears in meters
+const EAR_SPACING: f32 = 0.2;
+
+/// How the dry and wet signals are put together
+#[derive(Enum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MixMode {
+    /// Constant power across the mix
+    #[default]
+    #[name = "Equal Power"]
+    EqualPower,
+    /// Gains add up to one across the mix
+    Linear,
+    /// Independent dry and wet gains, the mix is ignored
+    #[name = "Dry/Wet"]
+    DryWet,
+    /// Wet only for an aux return, as if the mix was at 100%
+    Send,
+}
 
 // Main DSP
 pub struct Reverb {
+    sample_rate: f32,
     mix: f32,
-    fdn: HouseholderFDN<{ DELAYS.len() }>,
-    junction: ChannelJunction<2, { DELAYS.len() }>,
+    mix_mode: MixMode,
+    dry_gain: f32,
+    wet_gain: f32,
+    // Side gain of the wet signal, 1 leaves it as it is
+    width: f32,
+    // How many dB the wet signal drops per dB the input goes over the threshold
+    duck_amount: f32,
+    // In dB
+    duck_threshold: f32,
+    ducker: EnvelopeFollower,
+    gate_enabled: bool,
+    gate: Gate,
+    early_level: f32,
+    late_level: f32,
+    // Times in seconds and rates in Hz, turned into samples for the current sample rate
+    size: f32,
+    decay: f32,
+    predelay_time: f32,
+    mod_rate: f32,
+    mod_depth: f32,
+    seed: u32,
+    strategy: DelayStrategy,
+    crossfade: f32,
+    freeze: bool,
+    // Ramps between 0 and 1 when Freeze changes
+    freeze_amount: f32,
+    freeze_step: f32,
+    reverse: bool,
+    reverser: Reverser,
+    // Holds the dry signal back to line up with the reversed windows
+    dry_delay: [FractionalDelay; 2],
+    // Holds a sidechain key back by the same amount, so ducking and gating follow the dry hits
+    key_delay: [FractionalDelay; 2],
+    // Whether the sidechain key has had signal since the last reset. Hosts feed silence when
+    // nothing is routed there, which would otherwise shut the gate for good
+    key_connected: bool,
+    bass_multiplier: f32,
+    treble_multiplier: f32,
+    // Delay line lengths in samples at full size, and scaled by the size
+    delays: [f32; MAX_ORDER],
+    lines: [f32; MAX_ORDER],
+    // The lengths or decay changed since the tank last took them
+    tuning_changed: bool,
+    predelay: [FractionalDelay; 2],
+    early: EarlyReflections,
+    diffusers: [AllpassCascade<4>; 2],
+    eq: OutputEq,
+    fdn: FeedbackDelayNetwork<MAX_ORDER, SwitchableMatrix<MAX_ORDER>>,
+    junction: ChannelJunction<2, MAX_ORDER>,
 }
 
 impl Reverb {
-    pub fn new(mix: f32, lowpass: f32, time: f32, max_delay: usize) -> Self {
-        let mut fdn = HouseholderFDN::<{ DELAYS.len() }>::new(
-            DELAYS.map(|delay| (delay * DEFAULT_SAMPLE_RATE as f32) as usize),
-            time,
-            max_delay,
-        );
+    /// Damping cutoff in Hz and decay in seconds. The buffers are sized for the default sample
+    /// rate until `prepare` is called
+    pub fn new(mix: f32, lowpass: f32, decay: f32) -> Self {
+        let mut fdn =
+            FeedbackDelayNetwork::<MAX_ORDER, SwitchableMatrix<_>>::new([1.0; MAX_ORDER], 1.0, 1);
 
         fdn.set_cutoff(lowpass);
+        fdn.set_low_cut(0.0);
+        fdn.set_order(DEFAULT_ORDER);
+
+        let mut junction = ChannelJunction::<2, MAX_ORDER>::default();
+        junction.set_lines(DEFAULT_ORDER);
+
+        let diffusers = DIFFUSER_DELAYS.map(|delays| AllpassCascade::new(delays, 0.0, 1));
+
+        let mut reverb = Self {
+            sample_rate: DEFAULT_SAMPLE_RATE as f32,
+            mix,
+            mix_mode: MixMode::default(),
+            dry_gain: 1.0,
+            wet_gain: 1.0,
+            width: 1.0,
+            duck_amount: 0.0,
+            duck_threshold: 0.0,
+            ducker: EnvelopeFollower::default(),
+            gate_enabled: false,
+            gate: Gate::default(),
+            early_level: 0.0,
+            late_level: 1.0,
+            size: MAX_SIZE,
+            decay,
+            predelay_time: 0.0,
+            mod_rate: 0.0,
+            mod_depth: 0.0,
+            seed: DEFAULT_SEED,
+            strategy: DelayStrategy::default(),
+            crossfade: SIZE_CROSSFADE,
+            freeze: false,
+            freeze_amount: 0.0,
+            freeze_step: 1.0,
+            reverse: false,
+            reverser: Reverser::new(MAX_REVERSE / 4.0, DEFAULT_SAMPLE_RATE as f32),
+            dry_delay: [(); 2].map(|_| FractionalDelay::new(1, 1.0)),
+            key_delay: [(); 2].map(|_| FractionalDelay::new(1, 1.0)),
+            key_connected: false,
+            bass_multiplier: 1.0,
+            treble_multiplier: 1.0,
+            delays: [1.0; MAX_ORDER],
+            lines: [1.0; MAX_ORDER],
+            tuning_changed: true,
+            predelay: [(); 2].map(|_| FractionalDelay::new(1, 1.0)),
+            early: EarlyReflections::new(Room::default(), DEFAULT_SAMPLE_RATE as f32),
+            diffusers,
+            eq: OutputEq::new(DEFAULT_SAMPLE_RATE as f32),
+            fdn,
+            junction,
+        };
+        reverb.prepare(DEFAULT_SAMPLE_RATE as f32, DEFAULT_MAX_BLOCK_SIZE);
+        reverb
+    }
+
+    /// Size the buffers and turn every time, length and frequency into samples for a sample
+    /// rate, then clear the state. Nothing depends on the block size yet. This allocates, so it
+    /// must not be called from the audio thread
+    pub fn prepare(&mut self, sample_rate: f32, _max_block_size: usize) {
+        self.sample_rate = sample_rate;
+
+        // Room for the longest line at full size and the modulation to swing
+        let max_delay = ((MAX_SIZE * MAX_DELAY + MAX_MOD_DEPTH) * sample_rate) as usize;
+        self.fdn.set_max_delays(max_delay);
+        self.fdn.set_sample_rate(sample_rate);
+        self.early.set_sample_rate(sample_rate);
+        self.eq.set_sample_rate(sample_rate);
+        self.ducker.set_sample_rate(sample_rate);
+        self.gate.set_sample_rate(sample_rate);
+        self.reverser.set_sample_rate(sample_rate);
+
+        for (diffuser, delays) in self.diffusers.iter_mut().zip(DIFFUSER_DELAYS) {
+            let delays = delays.map(|delay| delay * sample_rate);
+            diffuser.set_max_delays(get_max_float(&delays) as usize + 1);
+            diffuser.set_delays(delays);
+        }
 
-        let junction = ChannelJunction::<2, { DELAYS.len() }>::default();
+        self.freeze_step = 1.0 / (FREEZE_TIME * sample_rate);
 
-        Self { mix, fdn, junction }
+        for delay in self.predelay.iter_mut() {
+            delay.set_max_delay((MAX_PREDELAY * sample_rate) as usize);
+        }
+        self.update_crossfade();
+        // The dry signal jumps to a new delay together with the window, see `process`
+        for delay in self.dry_delay.iter_mut().chain(self.key_delay.iter_mut()) {
+            delay.set_max_delay(self.reverser.buffer.len());
+            delay.set_crossfade(0);
+            delay.set_delay(self.reverser.latency() as f32);
+        }
+
+        self.delays = generate_delays(self.seed, self.strategy, sample_rate);
+        self.update_lines();
+        self.update_tuning();
+        self.set_predelay(self.predelay_time);
+        self.set_modulation(self.mod_rate, self.mod_depth);
+
+        // Start from silence on the new lengths rather than fading to them
+        self.reset();
     }
 
     pub fn set_mix(&mut self, mix: f32) {
         self.mix = mix;
     }
 
-    pub fn set_gain(&mut self, gain: f32) {
-        self.fdn.set_gain(gain);
+    pub fn set_mix_mode(&mut self, mode: MixMode) {
+        self.mix_mode = mode;
     }
 
-    pub fn set_delays(&mut self, delays: [usize; DELAYS.len()]) {
-        self.fdn.set_delays(delays);
+    /// Gains used by the Dry/Wet mix mode
+    pub fn set_dry_wet(&mut self, dry: f32, wet: f32) {
+        self.dry_gain = dry;
+        self.wet_gain = wet;
     }
 
-    pub fn set_max_delays(&mut self, max_delay: usize) -> () {
-        self.fdn.set_max_delays(max_delay);
+    /// Stereo width of the wet signal, from mono at 0 through unchanged at 1 to twice the side
+    /// signal at 2
+    pub fn set_width(&mut self, width: f32) {
+        self.width = width;
+    }
+
+    /// Turn the wet signal down while the input is loud, so the tail blooms in the gaps. Amount
+    /// between 0 and 1, threshold in dB, attack and release in seconds
+    pub fn set_ducking(&mut self, amount: f32, threshold: f32, attack: f32, release: f32) {
+        self.duck_amount = amount;
+        self.duck_threshold = threshold;
+        if attack != self.ducker.attack || release != self.ducker.release {
+            self.ducker.set_times(attack, release);
+        }
+    }
+
+    /// Cut the wet signal off a while after the input drops, for gated reverb. Threshold in dB,
+    /// hold and release in seconds
+    pub fn set_gate(&mut self, enabled: bool, threshold: f32, hold: f32, release: f32) {
+        self.gate_enabled = enabled;
+        let threshold = 10f32.powf(threshold / 20.0);
+        if threshold != self.gate.threshold
+            || hold != self.gate.hold
+            || release != self.gate.release
+        {
+            self.gate.set(threshold, hold, release);
+        }
+    }
+
+    /// Time in seconds for the tail to fall by 60 dB
+    pub fn set_decay(&mut self, decay: f32) {
+        if decay != self.decay {
+            self.decay = decay;
+            self.tuning_changed = true;
+        }
+    }
+
+    /// Pitch shift part of the feedback so the tail climbs, amount between 0 and 1
+    pub fn set_shimmer(&mut self, amount: f32, interval: ShimmerInterval) {
+        self.fdn.set_shimmer(amount, interval);
+    }
+
+    /// Hold the tail forever and stop taking in new input
+    pub fn set_freeze(&mut self, freeze: bool) {
+        self.freeze = freeze;
+    }
+
+    /// Play the wet signal back in reversed windows of a length in seconds, so the tail swells up
+    /// to the dry signal instead of falling away from it. The dry signal is held back to match,
+    /// see `latency`
+    pub fn set_reverse(&mut self, reverse: bool, time: f32) {
+        if time != self.reverser.time {
+            self.reverser.set_time(time);
+        }
+
+        // Nothing went through while it was off, start from silence on the new window
+        if reverse && !self.reverse {
+            self.reverser.reset();
+            for delay in self.dry_delay.iter_mut().chain(self.key_delay.iter_mut()) {
+                delay.reset();
+            }
+        }
+        self.reverse = reverse;
+    }
+
+    /// Samples the output lags the input by, for the host to compensate. A new reverse window
+    /// length only shows up here once the current window has played out
+    pub fn latency(&self) -> usize {
+        if self.reverse {
+            self.reverser.latency()
+        } else {
+            0
+        }
+    }
+
+    /// Decay time of the bass and treble relative to the mids
+    pub fn set_decay_multipliers(&mut self, bass: f32, treble: f32) {
+        if bass != self.bass_multiplier || treble != self.treble_multiplier {
+            self.bass_multiplier = bass;
+            self.treble_multiplier = treble;
+            self.tuning_changed = true;
+        }
+    }
+
+    /// Frequencies in Hz splitting the bass, mid and treble decay
+    pub fn set_crossovers(&mut self, low: f32, high: f32) {
+        self.fdn.set_crossovers(low, high);
     }
 
+    /// Gains of the early reflections and the late tail in the wet signal
+    pub fn set_levels(&mut self, early: f32, late: f32) {
+        self.early_level = early;
+        self.late_level = late;
+    }
+
+    pub fn set_room(&mut self, room: Room) {
+        self.early.set_room(room);
+    }
+
+    /// How much the input is smeared before it enters the tank, between 0 and 1
+    pub fn set_diffusion(&mut self, diffusion: f32) {
+        for diffuser in self.diffusers.iter_mut() {
+            diffuser.set_gain(diffusion * MAX_DIFFUSION);
+        }
+    }
+
+    /// Scale of the delay line lengths, up to MAX_SIZE
+    pub fn set_size(&mut self, size: f32) {
+        let size = size.min(MAX_SIZE);
+        if size != self.size {
+            self.size = size;
+            self.update_lines();
+        }
+    }
+
+    /// Time in seconds a delay line takes to crossfade to a new length when Size changes, up to
+    /// MAX_SIZE_CROSSFADE. A new room, pre-delay or matrix crossfades over the same time
+    pub fn set_crossfade(&mut self, time: f32) {
+        let time = time.min(MAX_SIZE_CROSSFADE);
+        if time != self.crossfade {
+            self.crossfade = time;
+            self.update_crossfade();
+        }
+    }
+
+    fn update_crossfade(&mut self) {
+        let crossfade = (self.crossfade * self.sample_rate) as usize;
+        self.fdn.set_crossfade(crossfade);
+        self.early.set_crossfade(crossfade);
+        for delay in self.predelay.iter_mut() {
+            delay.set_crossfade(crossfade);
+        }
+    }
+
+    fn update_lines(&mut self) {
+        let mut delays = self.delays.map(|delay| delay * self.size);
+
+        // Scaled primes aren't whole or coprime any more. Move each one up to the next prime
+        // that isn't taken, shortest first like `generate_delays`
+        if self.strategy == DelayStrategy::MutuallyPrime {
+            let bits = MAX_ORDER.trailing_zeros();
+            let mut previous = 0;
+            for ii in 0..MAX_ORDER {
+                let delay = &mut delays[ii.reverse_bits() >> (usize::BITS - bits)];
+                let length = next_prime((delay.round() as usize).max(previous + 1));
+                previous = length;
+                *delay = length as f32;
+            }
+        }
+
+        self.lines = delays;
+        self.tuning_changed = true;
+    }
+
+    /// Hand the lengths and decay to the tank, which works out every line's loop gain from both.
+    /// Called once a block so several changes cost one update
+    fn update_tuning(&mut self) {
+        if self.tuning_changed {
+            self.tuning_changed = false;
+            self.fdn.set_tuning(
+                self.lines,
+                self.decay * self.sample_rate,
+                self.bass_multiplier,
+                self.treble_multiplier,
+            );
+        }
+    }
+
+    /// Pick the delay line lengths and the random feedback matrix. Only generates a new set when
+    /// something changed
+    pub fn set_lengths(&mut self, seed: u32, strategy: DelayStrategy) {
+        if seed != self.seed || strategy != self.strategy {
+            self.seed = seed;
+            self.strategy = strategy;
+            self.delays = generate_delays(seed, strategy, self.sample_rate);
+            self.update_lines();
+            self.fdn.set_matrix_seed(seed);
+        }
+    }
+
+    /// How the delay lines of the tank feed back into each other
+    pub fn set_matrix(&mut self, matrix: Matrix) {
+        self.fdn.set_matrix(matrix);
+    }
+
+    /// Number of delay lines in the tank, a power of two up to MAX_ORDER. Fewer lines use
+    /// less CPU but take longer to build up a dense tail. Lines are added and dropped straight
+    /// away, so a change mid tail can click
+    pub fn set_order(&mut self, order: usize) {
+        self.fdn.set_order(order);
+        self.junction.set_lines(order);
+    }
+
+    /// How the stereo input is spread over the tank and read back out of it
+    pub fn set_routing(&mut self, routing: Routing) {
+        self.junction.set_routing(routing);
+    }
+
+    /// How quickly the tail becomes diffuse, from parallel combs at 0 to the full matrix at 1
+    pub fn set_tank_diffusion(&mut self, diffusion: f32) {
+        self.fdn.set_density(diffusion);
+    }
+
+    /// Delay before the wet signal in seconds, at least one sample
+    pub fn set_predelay(&mut self, predelay: f32) {
+        self.predelay_time = predelay;
+        for delay in self.predelay.iter_mut() {
+            delay.set_delay(predelay * self.sample_rate);
+        }
+    }
+
+    /// Rate in Hz, depth in seconds
+    pub fn set_modulation(&mut self, rate: f32, depth: f32) {
+        self.mod_rate = rate;
+        self.mod_depth = depth;
+        self.fdn
+            .set_modulation(rate / self.sample_rate, depth * self.sample_rate);
+    }
+
+    pub fn set_modulation_shape(&mut self, shape: LfoShape) {
+        self.fdn.set_modulation_shape(shape);
+    }
+
+    /// How the tank's delay lines read between samples, matters most for modulation and Size
+    pub fn set_interpolation(&mut self, interpolation: Interpolation) {
+        self.fdn.set_interpolation(interpolation);
+    }
+
+    /// Low and high cut of the wet signal in Hz, and tilt in dB around TILT_FREQUENCY. Positive
+    /// tilt brightens
+    pub fn set_eq(&mut self, low_cut: f32, high_cut: f32, tilt: f32) {
+        self.eq.set_cuts(low_cut, high_cut);
+        self.eq.set_tilt(tilt);
+    }
+
+    /// Damping cutoff in Hz
     pub fn set_cutoff(&mut self, cutoff: f32) {
         self.fdn.set_cutoff(cutoff);
     }
 
+    /// Highpass cutoff in Hz inside the tank, so the lows die away faster than the rest
+    pub fn set_tank_low_cut(&mut self, cutoff: f32) {
+        self.fdn.set_low_cut(cutoff);
+    }
+
     pub fn reset(&mut self) {
+        for delay in self.predelay.iter_mut() {
+            delay.reset();
+        }
+        self.early.reset();
+        for diffuser in self.diffusers.iter_mut() {
+            diffuser.reset();
+        }
+        self.eq.reset();
+        self.ducker.reset();
+        self.gate.reset();
+        self.reverser.reset();
+        for delay in self.dry_delay.iter_mut().chain(self.key_delay.iter_mut()) {
+            delay.reset();
+        }
+        // Nothing has come in on the sidechain yet
+        self.key_connected = false;
         self.fdn.reset();
     }
 
     pub fn process_buffer_slice(&mut self, channels: &mut [&mut [f32]]) {
-        // Simple equal power dry/wet mix
-        let (wet_t, dry_t) = (self.mix.sqrt(), (1.0 - self.mix).sqrt());
+        self.process(channels, None);
+    }
+
+    /// Like `process_buffer_slice`, with the ducking and the gate driven by a separate stereo key
+    /// signal of the same length instead of the input. The input keys them until the first sample
+    /// of signal on the key after a reset, from then on the key is followed through any silence
+    pub fn process_buffer_slice_keyed(&mut self, channels: &mut [&mut [f32]], key: &[&mut [f32]]) {
+        self.process(channels, Some(key));
+    }
+
+    fn process(&mut self, channels: &mut [&mut [f32]], key: Option<&[&mut [f32]]>) {
+        self.update_tuning();
+
+        let (wet_t, dry_t) = match self.mix_mode {
+            MixMode::EqualPower => (self.mix.sqrt(), (1.0 - self.mix).sqrt()),
+            MixMode::Linear => (self.mix, 1.0 - self.mix),
+            MixMode::DryWet => (self.wet_gain, self.dry_gain),
+            MixMode::Send => (1.0, 0.0),
+        };
+
+        let freeze = if self.freeze { 1.0 } else { 0.0 };
 
         for ii in 0..channels[0].len() {
-            let samples = [channels[0][ii], channels[1][ii]];
+            if self.freeze_amount != freeze {
+                self.freeze_amount +=
+                    (freeze - self.freeze_amount).clamp(-self.freeze_step, self.freeze_step);
+                self.fdn.set_freeze(self.freeze_amount);
+            }
 
-            let output = self
+            // Frozen, nothing new gets into the reverb
+            let input_gain = 1.0 - self.freeze_amount;
+            let wet = [
+                self.predelay[0].tick(channels[0][ii]) * input_gain,
+                self.predelay[1].tick(channels[1][ii]) * input_gain,
+            ];
+
+            let early = self.early.tick(wet);
+
+            let samples = [
+                self.diffusers[0].tick(wet[0]),
+                self.diffusers[1].tick(wet[1]),
+            ];
+
+            let late = self
                 .junction
                 .join(self.fdn.tick(self.junction.split(samples)));
 
-            channels[0][ii] = (channels[0][ii] * dry_t) + (output[0] * wet_t);
-            channels[1][ii] = (channels[1][ii] * dry_t) + (output[1] * wet_t);
+            let output = self
+                .eq
+                .tick([0, 1].map(|ch| early[ch] * self.early_level + late[ch] * self.late_level));
+
+            // Mid/side width, https://en.wikipedia.org/wiki/Stereophonic_sound#M/S_technique
+            let mid = (output[0] + output[1]) * 0.5;
+            let side = (output[0] - output[1]) * 0.5 * self.width;
+            let output = [mid + side, mid - side];
+
+            let (output, dry) = if self.reverse {
+                let output = self.reverser.tick(output);
+
+                // A new window length starts at a window boundary, where the reversed signal is
+                // faded out. Move the dry signal to its new delay at the same sample, crossfading
+                // between reads seconds apart would double it
+                let latency = self.reverser.latency() as f32;
+                if latency != self.dry_delay[0].delay() {
+                    for delay in self.dry_delay.iter_mut().chain(self.key_delay.iter_mut()) {
+                        delay.set_delay(latency);
+                    }
+                }
+
+                (
+                    output,
+                    [
+                        self.dry_delay[0].tick(channels[0][ii]),
+                        self.dry_delay[1].tick(channels[1][ii]),
+                    ],
+                )
+            } else {
+                (output, [channels[0][ii], channels[1][ii]])
+            };
+
+            // Keyed from the dry signal unless there's a sidechain, which is held back with it
+            // when reversed. Ducks by the amount it goes over the threshold
+            let key = match key {
+                Some(key) => {
+                    let key = if self.reverse {
+                        [
+                            self.key_delay[0].tick(key[0][ii]),
+                            self.key_delay[1].tick(key[1][ii]),
+                        ]
+                    } else {
+                        [key[0][ii], key[1][ii]]
+                    };
+                    self.key_connected |= key != [0.0; 2];
+                    if self.key_connected {
+                        key
+                    } else {
+                        dry
+                    }
+                }
+                None => dry,
+            };
+            let key = key[0].abs().max(key[1].abs());
+            let envelope = self.ducker.tick(key);
+            let duck = if self.duck_amount > 0.0 {
+                let over = (20.0 * envelope.log10() - self.duck_threshold).max(0.0);
+                10f32.powf(-over * self.duck_amount / 20.0)
+            } else {
+                1.0
+            };
+
+            let gate = self.gate.tick(key);
+            let wet_gain = if self.gate_enabled {
+                wet_t * duck * gate
+            } else {
+                wet_t * duck
+            };
+
+            channels[0][ii] = (dry[0] * dry_t) + (output[0] * wet_gain);
+            channels[1][ii] = (dry[1] * dry_t) + (output[1] * wet_gain);
         }
     }
 }
 
+/// How the channels are spread over the delay lines and read back out of them
+#[derive(Enum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Routing {
+    /// Each channel feeds and reads its own block of lines
+    #[default]
+    Block,
+    /// Every channel feeds and reads all of the lines, each with its own pattern of signs
+    Orthogonal,
+}
+
+// Input (b) and output (c) gains between the channels and the delay lines,
+// https://ccrma.stanford.edu/~jos/pasp/FDN_Reverberation.html. Joining a split gives back the
+// input, and every routing puts the same energy into the lines
 struct ChannelJunction<const INPUT: usize, const OUTPUT: usize> {
-    input_buffer: [f32; INPUT],
-    output_buffer: [f32; OUTPUT],
+    // Outputs in use, the rest are left silent
+    lines: usize,
+    routing: Routing,
+    inputs: [[f32; OUTPUT]; INPUT],
+    outputs: [[f32; OUTPUT]; INPUT],
 }
 
 impl<const INPUT: usize, const OUTPUT: usize> Default for ChannelJunction<INPUT, OUTPUT> {
     fn default() -> Self {
-        Self {
-            input_buffer: [0.0; INPUT],
-            output_buffer: [0.0; OUTPUT],
-        }
+        let mut junction = Self {
+            lines: OUTPUT,
+            routing: Routing::default(),
+            inputs: [[0.0; OUTPUT]; INPUT],
+            outputs: [[0.0; OUTPUT]; INPUT],
+        };
+        junction.update_gains();
+        junction
     }
 }
 
 impl<const INPUT: usize, const OUTPUT: usize> ChannelJunction<INPUT, OUTPUT> {
+    fn set_lines(&mut self, lines: usize) {
+        let lines = lines.clamp(INPUT, OUTPUT);
+        if lines != self.lines {
+            self.lines = lines;
+            self.update_gains();
+        }
+    }
+
+    /// Changing the routing reads the tail back differently straight away, so it can click
+    fn set_routing(&mut self, routing: Routing) {
+        if routing != self.routing {
+            self.routing = routing;
+            self.update_gains();
+        }
+    }
+
+    fn update_gains(&mut self) {
+        let section_len = self.lines / INPUT;
+
+        for (channel, (inputs, outputs)) in self
+            .inputs
+            .iter_mut()
+            .zip(self.outputs.iter_mut())
+            .enumerate()
+        {
+            *inputs = [0.0; OUTPUT];
+            *outputs = [0.0; OUTPUT];
+
+            match self.routing {
+                Routing::Block => {
+                    let section = channel * section_len..(channel + 1) * section_len;
+                    for ii in section {
+                        inputs[ii] = 1.0;
+                        outputs[ii] = 1.0 / section_len as f32;
+                    }
+                }
+                Routing::Orthogonal => {
+                    // Every line gets every channel with a random sign, and the channels are kept
+                    // apart by rows of a Sylvester Hadamard matrix. Each channel reads back an
+                    // interleaved set of lines, so even a mono input comes out decorrelated
+                    // https://en.wikipedia.org/wiki/Hadamard_matrix#Sylvester's_construction
+                    let gain = (1.0 / INPUT as f32).sqrt();
+                    let mut random = Random::new(JUNCTION_SEED);
+                    for ii in 0..self.lines {
+                        let flip = random.next_u32() & 1 == 1;
+                        let row = (ii & channel).count_ones() % 2 == 1;
+                        let sign = if flip != row { -1.0 } else { 1.0 };
+
+                        inputs[ii] = sign * gain;
+                        if (ii / INPUT) % INPUT == channel {
+                            outputs[ii] = sign / (gain * section_len as f32);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
     fn split(&self, input: [f32; INPUT]) -> [f32; OUTPUT] {
-        let section_len = OUTPUT / INPUT;
-        let mut curr_section_len = 0;
-        let mut section_index = 0;
+        let mut output = [0.0; OUTPUT];
 
-        self.output_buffer.map(|_ii| {
-            let output = input[section_index];
-            curr_section_len += 1;
-            if curr_section_len >= section_len {
-                curr_section_len = 0;
-                section_index += 1;
+        for (channel, gains) in self.inputs.iter().enumerate() {
+            for (sample, gain) in output[..self.lines].iter_mut().zip(gains) {
+                *sample += input[channel] * gain;
             }
-            output
-        })
+        }
+
+        output
     }
 
     fn join(&self, output: [f32; OUTPUT]) -> [f32; INPUT] {
-        let section_len = OUTPUT / INPUT;
-        let mut section_index = 0;
-        let avg = 1.0 / section_len as f32;
-
-        self.input_buffer.map(|_ii| {
-            let section_end = section_index + section_len;
-            let average = output[section_index..section_end].iter().sum::<f32>() * avg;
-            section_index = section_end;
-            average
+        self.outputs.map(|gains| {
+            output[..self.lines]
+                .iter()
+                .zip(gains)
+                .map(|(sample, gain)| sample * gain)
+                .sum()
         })
     }
 }
@@ -173,245 +865,2051 @@ trait MultiSignal<const CHANNELS: usize> {
     fn reset(&mut self) -> ();
 }
 
-// Delay a signal a whole number of samples
-struct IntegerDelay {
+/// How a fractional delay reads between two stored samples
+#[derive(Enum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Interpolation {
+    Linear,
+    /// Third order Lagrange, https://ccrma.stanford.edu/~jos/pasp/Lagrange_Interpolation.html
+    #[default]
+    Cubic,
+    /// First order Thiran allpass, https://ccrma.stanford.edu/~jos/pasp/Thiran_Allpass_Interpolators.html
+    Thiran,
+}
+
+// Extra samples kept past the max delay so the interpolators can read their neighbours
+const INTERPOLATION_PADDING: usize = 4;
+
+// A position in a delay line that is read from
+#[derive(Clone, Copy, Default)]
+struct ReadHead {
+    delay: f32,
+    allpass_y1: f32,
+}
+
+// Delay a signal a fractional number of samples. Length changes crossfade from the old read
+// position to the new one instead of jumping
+struct FractionalDelay {
     buffer: Vec<f32>,
-    delay: usize,
+    heads: [ReadHead; 2],
+    current: usize,
+    target: f32,
+    fade: f32,
+    fade_step: f32,
+    modulation: f32,
     write_index: usize,
+    interpolation: Interpolation,
+    // Nothing written since the last reset, so a new length needs no fade
+    idle: bool,
+    // Samples written since the line was last emptied, anything older reads as silence
+    filled: usize,
 }
 
-impl IntegerDelay {
-    fn new(max_delay: usize, delay: usize) -> Self {
-        Self {
-            buffer: vec![0.0; max_delay],
-            delay: delay,
+impl FractionalDelay {
+    fn new(max_delay: usize, delay: f32) -> Self {
+        let mut line = Self {
+            buffer: vec![0.0; max_delay + INTERPOLATION_PADDING],
+            heads: [ReadHead::default(); 2],
+            current: 0,
+            target: 0.0,
+            fade: 1.0,
+            fade_step: 1.0,
+            modulation: 0.0,
             write_index: 0,
+            interpolation: Interpolation::default(),
+            idle: true,
+            filled: 0,
+        };
+        line.set_delay(delay);
+        line
+    }
+
+    fn max_delay(&self) -> f32 {
+        (self.buffer.len() - INTERPOLATION_PADDING) as f32
+    }
+
+    /// The length the line is at, or fading towards
+    fn delay(&self) -> f32 {
+        self.target
+    }
+
+    fn set_delay(&mut self, delay: f32) {
+        // All interpolators need at least one sample of delay, the cubic one reads a sample ahead
+        self.target = delay.clamp(1.0, self.max_delay());
+
+        if self.idle {
+            self.heads[self.current].delay = self.target;
+            self.fade = 1.0;
+            return;
         }
+
+        // A change that arrives mid fade waits for the running fade to finish
+        if self.fade >= 1.0 {
+            self.start_fade();
+        }
+    }
+
+    fn set_max_delay(&mut self, max_delay: usize) {
+        self.buffer.resize(max_delay + INTERPOLATION_PADDING, 0.0);
+        self.write_index %= self.buffer.len();
+
+        // Shrinking the buffer can't wait for a fade, the heads may now be out of bounds
+        let max_delay = self.max_delay();
+        for head in self.heads.iter_mut() {
+            head.delay = head.delay.clamp(1.0, max_delay);
+        }
+        self.set_delay(self.target);
+    }
+
+    /// Set how many samples a change of length takes to crossfade, 0 jumps immediately
+    fn set_crossfade(&mut self, samples: usize) {
+        self.fade_step = 1.0 / samples.max(1) as f32;
     }
 
-    fn set_delay(&mut self, delay: usize) -> () {
-        if delay == self.delay {
+    /// Offset both read heads by a number of samples, for chorusing the line
+    fn set_modulation(&mut self, offset: f32) {
+        self.modulation = offset;
+    }
+
+    fn set_interpolation(&mut self, interpolation: Interpolation) {
+        if interpolation == self.interpolation {
             return;
         }
 
-        let old_delay = self.delay;
+        self.interpolation = interpolation;
+        for head in self.heads.iter_mut() {
+            head.allpass_y1 = 0.0;
+        }
+    }
 
-        // Delay can't be longer than the max delay length
-        self.delay = delay.min(self.buffer.len() - 1);
+    fn start_fade(&mut self) {
+        let current = self.heads[self.current];
+        if self.target == current.delay {
+            return;
+        }
 
-        // Clear the buffer. It can be fun not to, however
-        if self.delay < old_delay {
-            for ii in self.delay..old_delay {
-                self.buffer[ii] = 0.0;
-            }
+        // The new head starts from the old allpass state, which is close enough while it is quiet
+        self.current = 1 - self.current;
+        self.heads[self.current] = ReadHead {
+            delay: self.target,
+            allpass_y1: current.allpass_y1,
+        };
+
+        // Without a crossfade the first sample is already fully on the new head
+        if self.fade_step >= 1.0 {
+            self.fade = 1.0;
+        } else {
+            self.fade = 0.0;
+        }
+    }
+
+    /// The sample written `offset` ticks ago, 0 being the most recent one
+    fn sample(&self, offset: usize) -> f32 {
+        if offset >= self.filled {
+            return 0.0;
         }
+
+        let len = self.buffer.len();
+        self.buffer[(self.write_index + len - offset) % len]
     }
 
-    fn set_max_delay(&mut self, max_delay: usize) -> () {
-        self.buffer.resize(max_delay, 0.0);
+    fn read(&mut self, head: usize) -> f32 {
+        let delay = (self.heads[head].delay + self.modulation).clamp(1.0, self.max_delay());
+
+        match self.interpolation {
+            Interpolation::Linear => {
+                let index = delay.floor();
+                let frac = delay - index;
+                let index = index as usize;
+
+                let x0 = self.sample(index);
+                x0 + frac * (self.sample(index + 1) - x0)
+            }
+            Interpolation::Cubic => {
+                let index = delay.floor();
+                let f = delay - index;
+                let index = index as usize;
+
+                let (xm1, x0, x1, x2) = (
+                    self.sample(index - 1),
+                    self.sample(index),
+                    self.sample(index + 1),
+                    self.sample(index + 2),
+                );
+
+                // Lagrange basis polynomials for the points at -1, 0, 1 and 2
+                let (fp1, fm1, fm2) = (f + 1.0, f - 1.0, f - 2.0);
+                -f * fm1 * fm2 / 6.0 * xm1 + fp1 * fm1 * fm2 / 2.0 * x0 - fp1 * f * fm2 / 2.0 * x1
+                    + fp1 * f * fm1 / 6.0 * x2
+            }
+            Interpolation::Thiran => {
+                // Keep the allpass part of the delay between 0.5 and 1.5 samples where it is well behaved
+                let index = (delay - 0.5).floor();
+                let frac = delay - index;
+                let index = index as usize;
+                let eta = (1.0 - frac) / (1.0 + frac);
+
+                let y = eta * self.sample(index) + self.sample(index + 1)
+                    - eta * self.heads[head].allpass_y1;
+                self.heads[head].allpass_y1 = y;
+                y
+            }
+        }
     }
 }
 
-impl Signal for IntegerDelay {
+impl Signal for FractionalDelay {
     fn tick(&mut self, input: f32) -> f32 {
-        let output = self.buffer[self.write_index];
         self.buffer[self.write_index] = input;
+        self.idle = false;
+        self.filled = (self.filled + 1).min(self.buffer.len());
+
+        let output = if self.fade < 1.0 {
+            // Linear crossfade, both heads read the same signal so their sum keeps its level
+            let old = self.read(1 - self.current);
+            let new = self.read(self.current);
+            let output = old + self.fade * (new - old);
+
+            self.fade += self.fade_step;
+            if self.fade >= 1.0 {
+                self.fade = 1.0;
+                self.start_fade();
+            }
+            output
+        } else {
+            self.read(self.current)
+        };
 
         self.write_index += 1;
-        if self.write_index >= self.delay {
+        if self.write_index >= self.buffer.len() {
             self.write_index = 0;
         }
         output
     }
 
-    fn reset(&mut self) -> () {
+    fn reset(&mut self) {
         for sample in self.buffer.iter_mut() {
             *sample = 0.0;
         }
+        self.empty();
     }
 }
 
-struct Feedback<T: Signal> {
-    signal: T,
-    value: f32,
+impl FractionalDelay {
+    /// Silence the line without touching the buffer, so it takes the same time however long the
+    /// line is. What was written before reads as zeros until it is overwritten
+    fn empty(&mut self) {
+        self.filled = 0;
+        for head in self.heads.iter_mut() {
+            head.allpass_y1 = 0.0;
+        }
+
+        // An empty line has nothing to fade, jump to the length it was heading for
+        self.heads[self.current].delay = self.target;
+        self.fade = 1.0;
+        self.idle = true;
+    }
+}
+
+// Schroeder allpass, spreads a signal out in time without colouring it
+// https://ccrma.stanford.edu/~jos/pasp/Allpass_Two_Combs.html
+struct SchroederAllpass {
+    delay: FractionalDelay,
+    delayed: f32,
     gain: f32,
 }
 
-impl<T: Signal> Signal for Feedback<T> {
+impl SchroederAllpass {
+    fn new(max_delay: usize, delay: f32, gain: f32) -> Self {
+        let mut allpass = Self {
+            delay: FractionalDelay::new(max_delay, 1.0),
+            delayed: 0.0,
+            gain,
+        };
+        allpass.set_delay(delay);
+        allpass
+    }
+
+    /// Delay in samples, at least 2
+    fn set_delay(&mut self, delay: f32) {
+        // The line's output is kept for the next tick, which accounts for one of the samples
+        self.delay.set_delay(delay - 1.0);
+    }
+
+    fn set_max_delay(&mut self, max_delay: usize) {
+        self.delay.set_max_delay(max_delay);
+    }
+
+    fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+}
+
+impl Signal for SchroederAllpass {
     fn tick(&mut self, input: f32) -> f32 {
-        let fback = input + self.value;
-        let output = self.signal.tick(fback) * self.gain;
-        self.value = output;
+        let state = input + self.gain * self.delayed;
+        let output = self.delayed - self.gain * state;
+
+        self.delayed = self.delay.tick(state);
         output
     }
 
-    fn reset(&mut self) -> () {
-        self.value = 0.0;
+    fn reset(&mut self) {
+        self.delay.reset();
+        self.delayed = 0.0;
     }
 }
 
-impl<T: Signal> Feedback<T> {
-    fn new(signal: T, gain: f32) -> Self {
+// Allpasses in series
+struct AllpassCascade<const STAGES: usize> {
+    stages: [SchroederAllpass; STAGES],
+}
+
+impl<const STAGES: usize> AllpassCascade<STAGES> {
+    fn new(delays: [f32; STAGES], gain: f32, max_delay: usize) -> Self {
         Self {
-            signal: signal,
-            gain: gain,
-            value: 0.0,
+            stages: delays.map(|delay| SchroederAllpass::new(max_delay, delay, gain)),
+        }
+    }
+
+    fn set_delays(&mut self, delays: [f32; STAGES]) {
+        for (stage, delay) in self.stages.iter_mut().zip(delays) {
+            stage.set_delay(delay);
+        }
+    }
+
+    fn set_max_delays(&mut self, max_delay: usize) {
+        for stage in self.stages.iter_mut() {
+            stage.set_max_delay(max_delay);
+        }
+    }
+
+    fn set_gain(&mut self, gain: f32) {
+        for stage in self.stages.iter_mut() {
+            stage.set_gain(gain);
+        }
+    }
+}
+
+impl<const STAGES: usize> Signal for AllpassCascade<STAGES> {
+    fn tick(&mut self, input: f32) -> f32 {
+        self.stages
+            .iter_mut()
+            .fold(input, |sample, stage| stage.tick(sample))
+    }
+
+    fn reset(&mut self) {
+        for stage in self.stages.iter_mut() {
+            stage.reset();
+        }
+    }
+}
+
+/// A rectangular room with one corner at the origin, in meters
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Room {
+    pub dimensions: [f32; 3],
+    pub source: [f32; 3],
+    /// Center of the listener's head, the ears are spread along the x axis
+    pub listener: [f32; 3],
+    /// Fraction of the energy the walls absorb, between 0 and 1
+    pub absorption: f32,
+}
+
+impl Default for Room {
+    fn default() -> Self {
+        Self {
+            dimensions: [12.0, 18.0, 6.0],
+            source: [6.0, 5.0, 1.5],
+            listener: [6.0, 11.0, 1.5],
+            absorption: 0.3,
+        }
+    }
+}
+
+// One echo of a multi tap delay
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+struct Tap {
+    delay: f32,
+    gain: f32,
+}
+
+impl Room {
+    /// The strongest reflections reaching `listener`, found with the image source method. Delays
+    /// are in seconds after the direct sound and gains are relative to it
+    /// https://doi.org/10.1121/1.382599
+    fn reflections(&self, listener: [f32; 3]) -> [Tap; EARLY_TAPS] {
+        let distance = |position: [f32; 3]| {
+            let mut sum = 0.0;
+            for axis in 0..3 {
+                sum += (position[axis] - listener[axis]).powi(2);
+            }
+            sum.sqrt()
+        };
+
+        let direct = distance(self.source).max(0.1);
+        let reflectance = (1.0 - self.absorption.clamp(0.0, 1.0)).sqrt();
+        let mut taps = [Tap::default(); EARLY_TAPS];
+
+        // Room offsets from -EARLY_ORDER to EARLY_ORDER on each axis
+        let span = 2 * EARLY_ORDER + 1;
+
+        for image_index in 0..(2 * span).pow(3) {
+            // Every combination of mirroring and room offset on each axis
+            let mut index = image_index;
+            let mut image = [0.0; 3];
+            let mut bounces = 0;
+            for ((coordinate, source), dimension) in
+                image.iter_mut().zip(self.source).zip(self.dimensions)
+            {
+                let mirrored = index % 2;
+                index /= 2;
+                let offset = index % span - EARLY_ORDER;
+                index /= span;
+
+                *coordinate = (1 - 2 * mirrored) as f32 * source + 2.0 * offset as f32 * dimension;
+                bounces += (offset - mirrored).abs() + offset.abs();
+            }
+
+            if bounces == 0 || bounces > EARLY_ORDER {
+                continue;
+            }
+
+            let length = distance(image);
+            let tap = Tap {
+                delay: (length - direct) / SPEED_OF_SOUND,
+                gain: reflectance.powi(bounces) * direct / length,
+            };
+
+            // Keep the taps sorted loudest first and drop the quietest
+            if tap.delay > MAX_EARLY_DELAY || tap.gain <= taps[EARLY_TAPS - 1].gain {
+                continue;
+            }
+            let mut position = EARLY_TAPS - 1;
+            while position > 0 && taps[position - 1].gain < tap.gain {
+                taps[position] = taps[position - 1];
+                position -= 1;
+            }
+            taps[position] = tap;
+        }
+
+        taps
+    }
+}
+
+// Early reflections of a room, rendered as a multi tap delay for each ear. A new room crossfades
+// from the old set of taps to the new one, so moving the source or the walls doesn't click
+struct EarlyReflections {
+    buffer: Vec<f32>,
+    write_index: usize,
+    room: Room,
+    // Two sets of taps for each ear, the one in use and the one faded from
+    taps: [[[Tap; EARLY_TAPS]; 2]; 2],
+    current: usize,
+    fade: f32,
+    fade_step: f32,
+    // The room changed during a fade, it gets its turn once the fade is done
+    pending: bool,
+    sample_rate: f32,
+}
+
+impl EarlyReflections {
+    fn new(room: Room, sample_rate: f32) -> Self {
+        let mut early = Self {
+            buffer: Vec::new(),
+            write_index: 0,
+            room,
+            taps: [[[Tap::default(); EARLY_TAPS]; 2]; 2],
+            current: 0,
+            fade: 1.0,
+            fade_step: 1.0,
+            pending: false,
+            sample_rate,
+        };
+        early.set_sample_rate(sample_rate);
+        early
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.buffer
+            .resize((MAX_EARLY_DELAY * sample_rate) as usize + 2, 0.0);
+        self.write_index %= self.buffer.len();
+
+        // Straight to the new taps, there's nothing sensible to fade from
+        self.update_taps(self.current);
+        self.fade = 1.0;
+        self.pending = false;
+    }
+
+    /// Set how many samples a new room takes to crossfade, 0 jumps immediately
+    fn set_crossfade(&mut self, samples: usize) {
+        self.fade_step = 1.0 / samples.max(1) as f32;
+    }
+
+    fn set_room(&mut self, room: Room) {
+        if room != self.room {
+            self.room = room;
+            if self.fade >= 1.0 {
+                self.start_fade();
+            } else {
+                self.pending = true;
+            }
+        }
+    }
+
+    fn start_fade(&mut self) {
+        self.pending = false;
+        self.current = 1 - self.current;
+        self.update_taps(self.current);
+
+        // Without a crossfade the first sample is already fully on the new taps
+        self.fade = if self.fade_step >= 1.0 { 1.0 } else { 0.0 };
+    }
+
+    fn update_taps(&mut self, set: usize) {
+        for (ear, taps) in self.taps[set].iter_mut().enumerate() {
+            let mut listener = self.room.listener;
+            listener[0] += (ear as f32 - 0.5) * EAR_SPACING;
+
+            *taps = self.room.reflections(listener).map(|tap| Tap {
+                delay: tap.delay.max(0.0) * self.sample_rate,
+                gain: tap.gain,
+            });
+        }
+    }
+
+    fn read(&self, taps: &[[Tap; EARLY_TAPS]; 2]) -> [f32; 2] {
+        let len = self.buffer.len();
+        taps.map(|taps| {
+            taps.iter()
+                .map(|tap| {
+                    let index = tap.delay.floor();
+                    let frac = tap.delay - index;
+                    let index = self.write_index + len - index as usize;
+
+                    let x0 = self.buffer[index % len];
+                    let x1 = self.buffer[(index + len - 1) % len];
+                    (x0 + frac * (x1 - x0)) * tap.gain
+                })
+                .sum()
+        })
+    }
+}
+
+impl MultiSignal<2> for EarlyReflections {
+    fn tick(&mut self, input: [f32; 2]) -> [f32; 2] {
+        // The room has a single source, so both channels are played from it
+        self.buffer[self.write_index] = (input[0] + input[1]) * 0.5;
+
+        let output = if self.fade < 1.0 {
+            let old = self.read(&self.taps[1 - self.current]);
+            let new = self.read(&self.taps[self.current]);
+            let fade = self.fade;
+
+            self.fade += self.fade_step;
+            if self.fade >= 1.0 {
+                self.fade = 1.0;
+                if self.pending {
+                    self.start_fade();
+                }
+            }
+            [0, 1].map(|ear| old[ear] + fade * (new[ear] - old[ear]))
+        } else {
+            self.read(&self.taps[self.current])
+        };
+
+        self.write_index += 1;
+        if self.write_index >= self.buffer.len() {
+            self.write_index = 0;
+        }
+        output
+    }
+
+    fn reset(&mut self) {
+        for sample in self.buffer.iter_mut() {
+            *sample = 0.0;
+        }
+
+        // Nothing to fade in an empty buffer, jump to the latest room
+        if self.fade < 1.0 || self.pending {
+            self.update_taps(self.current);
+            self.fade = 1.0;
+            self.pending = false;
+        }
+    }
+}
+
+// Xorshift random numbers, https://www.jstatsoft.org/article/view/v008i14
+#[derive(Clone, Copy)]
+struct Random {
+    state: u32,
+}
+
+impl Random {
+    fn new(seed: u32) -> Self {
+        // An all zero state never changes
+        Self { state: seed.max(1) }
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state
+    }
+
+    /// Uniform between -1 and 1
+    fn next_bipolar(&mut self) -> f32 {
+        (self.next_u32() as f64 / u32::MAX as f64 * 2.0 - 1.0) as f32
+    }
+}
+
+/// Waveform of the delay modulation
+#[derive(Enum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LfoShape {
+    #[default]
+    Sine,
+    /// Random values with a cosine glide between them
+    #[name = "Smooth Random"]
+    SmoothRandom,
+}
+
+// Low frequency oscillator between -1 and 1
+#[derive(Clone, Copy)]
+struct Lfo {
+    phase: f32,
+    increment: f32,
+    shape: LfoShape,
+    random: Random,
+    from: f32,
+    to: f32,
+}
+
+impl Lfo {
+    fn new(phase: f32, seed: u32) -> Self {
+        let mut random = Random::new(seed);
+        let (from, to) = (random.next_bipolar(), random.next_bipolar());
+
+        Self {
+            phase,
+            increment: 0.0,
+            shape: LfoShape::default(),
+            random,
+            from,
+            to,
+        }
+    }
+
+    /// Rate in cycles per sample
+    fn set_rate(&mut self, rate: f32) {
+        self.increment = rate;
+    }
+
+    fn set_shape(&mut self, shape: LfoShape) {
+        self.shape = shape;
+    }
+
+    fn tick(&mut self) -> f32 {
+        let output = match self.shape {
+            LfoShape::Sine => (TAU * self.phase).sin(),
+            LfoShape::SmoothRandom => {
+                let t = 0.5 - 0.5 * (PI * self.phase).cos();
+                self.from + (self.to - self.from) * t
+            }
+        };
+
+        self.phase += self.increment;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+            self.from = self.to;
+            self.to = self.random.next_bipolar();
+        }
+        output
+    }
+}
+
+struct Feedback<T: Signal> {
+    signal: T,
+    value: f32,
+    gain: f32,
+}
+
+impl<T: Signal> Signal for Feedback<T> {
+    fn tick(&mut self, input: f32) -> f32 {
+        let fback = input + self.value;
+        let output = self.signal.tick(fback) * self.gain;
+        self.value = output;
+        output
+    }
+
+    fn reset(&mut self) -> () {
+        self.value = 0.0;
+    }
+}
+
+impl<T: Signal> Feedback<T> {
+    fn new(signal: T, gain: f32) -> Self {
+        Self {
+            signal: signal,
+            gain: gain,
+            value: 0.0,
+        }
+    }
+
+    fn set_gain(&mut self, gain: f32) -> () {
+        self.gain = gain;
+    }
+}
+
+#[derive(Clone, Copy)]
+struct OnePole {
+    y1: f32,
+    a0: f32,
+    b1: f32,
+    cutoff: f32,
+    sample_rate: f32,
+}
+
+// // A one pole filter, https://ccrma.stanford.edu/~jos/fp/One_Pole.html
+impl Signal for OnePole {
+    fn tick(&mut self, input: f32) -> f32 {
+        self.y1 = input * self.a0 + self.y1 * self.b1;
+        self.y1
+    }
+
+    fn reset(&mut self) -> () {
+        self.y1 = 0.0;
+    }
+}
+
+impl OnePole {
+    #[cfg(test)]
+    fn new(cutoff: f32, sample_rate: f32) -> Self {
+        let mut filter = Self {
+            sample_rate,
+            ..Self::default()
+        };
+        filter.set_cutoff(cutoff);
+        filter
+    }
+
+    /// Cutoff in Hz
+    fn set_cutoff(&mut self, cutoff: f32) -> () {
+        self.cutoff = cutoff;
+
+        let x = (-TAU * (cutoff / self.sample_rate)).exp();
+        self.a0 = 1.0 - x;
+        self.b1 = x;
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.set_cutoff(self.cutoff);
+    }
+}
+
+impl Default for OnePole {
+    fn default() -> Self {
+        Self {
+            y1: 0.0,
+            a0: 1f32,
+            b1: 0.0,
+            // Passes everything until a cutoff is set
+            cutoff: f32::INFINITY,
+            sample_rate: DEFAULT_SAMPLE_RATE as f32,
+        }
+    }
+}
+
+// First order lowpass through the bilinear transform, unlike `OnePole` it fully rejects Nyquist.
+// Topology preserving form from https://www.native-instruments.com/fileadmin/ni_media/downloads/pdf/VAFilterDesign_2.1.0.pdf
+#[derive(Clone, Copy)]
+struct FirstOrderLowpass {
+    g: f32,
+    s: f32,
+    cutoff: f32,
+    sample_rate: f32,
+}
+
+impl Signal for FirstOrderLowpass {
+    fn tick(&mut self, input: f32) -> f32 {
+        let v = (input - self.s) * self.g;
+        let output = v + self.s;
+        self.s = output + v;
+        output
+    }
+
+    fn reset(&mut self) {
+        self.s = 0.0;
+    }
+}
+
+impl FirstOrderLowpass {
+    /// Cutoff in Hz
+    fn set_cutoff(&mut self, cutoff: f32) {
+        self.cutoff = cutoff;
+
+        let normalized = cutoff / self.sample_rate;
+        if normalized >= 0.5 {
+            self.g = 1.0;
+        } else {
+            let g = (PI * normalized).tan();
+            self.g = g / (1.0 + g);
+        }
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.set_cutoff(self.cutoff);
+    }
+}
+
+impl Default for FirstOrderLowpass {
+    fn default() -> Self {
+        Self {
+            g: 1.0,
+            s: 0.0,
+            cutoff: f32::INFINITY,
+            sample_rate: DEFAULT_SAMPLE_RATE as f32,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum BiquadType {
+    Lowpass,
+    Highpass,
+    LowShelf,
+    HighShelf,
+}
+
+// Second order filter with coefficients from the RBJ cookbook,
+// https://webaudio.github.io/Audio-EQ-Cookbook/audio-eq-cookbook.html
+// run in transposed direct form II, https://ccrma.stanford.edu/~jos/fp/Transposed_Direct_Forms.html
+#[derive(Clone, Copy)]
+struct Biquad {
+    kind: BiquadType,
+    frequency: f32,
+    q: f32,
+    // Shelf gain in dB
+    gain: f32,
+    sample_rate: f32,
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    s1: f32,
+    s2: f32,
+}
+
+impl Signal for Biquad {
+    fn tick(&mut self, input: f32) -> f32 {
+        let output = self.b0 * input + self.s1;
+        self.s1 = self.b1 * input - self.a1 * output + self.s2;
+        self.s2 = self.b2 * input - self.a2 * output;
+        output
+    }
+
+    fn reset(&mut self) {
+        self.s1 = 0.0;
+        self.s2 = 0.0;
+    }
+}
+
+impl Biquad {
+    /// Frequency in Hz, outside of 0 to Nyquist the filter passes everything
+    fn new(kind: BiquadType, frequency: f32, sample_rate: f32) -> Self {
+        let mut filter = Self {
+            kind,
+            frequency,
+            q: FRAC_1_SQRT_2,
+            gain: 0.0,
+            sample_rate,
+            b0: 1.0,
+            b1: 0.0,
+            b2: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+            s1: 0.0,
+            s2: 0.0,
+        };
+        filter.update_coefficients();
+        filter
+    }
+
+    /// Frequency in Hz
+    fn set_frequency(&mut self, frequency: f32) {
+        if frequency != self.frequency {
+            self.frequency = frequency;
+            self.update_coefficients();
+        }
+    }
+
+    /// Shelf gain in dB
+    fn set_gain(&mut self, gain: f32) {
+        if gain != self.gain {
+            self.gain = gain;
+            self.update_coefficients();
+        }
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.update_coefficients();
+    }
+
+    fn update_coefficients(&mut self) {
+        let normalized = self.frequency / self.sample_rate;
+        if !(normalized > 0.0 && normalized < 0.5) {
+            (self.b0, self.b1, self.b2, self.a1, self.a2) = (1.0, 0.0, 0.0, 0.0, 0.0);
+            return;
+        }
+
+        let (sin, cos) = (TAU * normalized).sin_cos();
+        let alpha = sin / (2.0 * self.q);
+        let a = 10f32.powf(self.gain / 40.0);
+        let shelf = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match self.kind {
+            BiquadType::Lowpass => (
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            BiquadType::Highpass => (
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            BiquadType::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+                (a + 1.0) + (a - 1.0) * cos + shelf,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - shelf,
+            ),
+            BiquadType::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+                (a + 1.0) - (a - 1.0) * cos + shelf,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - shelf,
+            ),
+        };
+
+        self.b0 = b0 / a0;
+        self.b1 = b1 / a0;
+        self.b2 = b2 / a0;
+        self.a1 = a1 / a0;
+        self.a2 = a2 / a0;
+    }
+}
+
+// Tone shaping for the wet signal, a low cut and high cut with a tilt around TILT_FREQUENCY
+struct OutputEq {
+    low_cut: [Biquad; 2],
+    high_cut: [Biquad; 2],
+    low_shelf: [Biquad; 2],
+    high_shelf: [Biquad; 2],
+}
+
+impl OutputEq {
+    /// Starts flat
+    fn new(sample_rate: f32) -> Self {
+        let filter = |kind, frequency| [Biquad::new(kind, frequency, sample_rate); 2];
+        Self {
+            low_cut: filter(BiquadType::Highpass, 0.0),
+            high_cut: filter(BiquadType::Lowpass, f32::INFINITY),
+            low_shelf: filter(BiquadType::LowShelf, TILT_FREQUENCY),
+            high_shelf: filter(BiquadType::HighShelf, TILT_FREQUENCY),
+        }
+    }
+
+    fn filters(&mut self) -> impl Iterator<Item = &mut Biquad> {
+        self.low_cut
+            .iter_mut()
+            .chain(self.high_cut.iter_mut())
+            .chain(self.low_shelf.iter_mut())
+            .chain(self.high_shelf.iter_mut())
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        for filter in self.filters() {
+            filter.set_sample_rate(sample_rate);
+        }
+    }
+
+    /// Frequencies in Hz
+    fn set_cuts(&mut self, low: f32, high: f32) {
+        for filter in self.low_cut.iter_mut() {
+            filter.set_frequency(low);
+        }
+        for filter in self.high_cut.iter_mut() {
+            filter.set_frequency(high);
+        }
+    }
+
+    /// Difference in dB between the highs and lows, split evenly between the two shelves
+    fn set_tilt(&mut self, tilt: f32) {
+        for filter in self.low_shelf.iter_mut() {
+            filter.set_gain(-tilt / 2.0);
+        }
+        for filter in self.high_shelf.iter_mut() {
+            filter.set_gain(tilt / 2.0);
+        }
+    }
+}
+
+impl MultiSignal<2> for OutputEq {
+    fn tick(&mut self, input: [f32; 2]) -> [f32; 2] {
+        [0, 1].map(|ch| {
+            let output = self.low_cut[ch].tick(input[ch]);
+            let output = self.high_cut[ch].tick(output);
+            let output = self.low_shelf[ch].tick(output);
+            self.high_shelf[ch].tick(output)
+        })
+    }
+
+    fn reset(&mut self) {
+        for filter in self.filters() {
+            filter.reset();
+        }
+    }
+}
+
+// Peak envelope with separate attack and release times,
+// https://www.musicdsp.org/en/latest/Analysis/136-envelope-follower-with-different-attack-and-release.html
+struct EnvelopeFollower {
+    envelope: f32,
+    // Seconds to get within 1/e of a new level
+    attack: f32,
+    release: f32,
+    attack_coefficient: f32,
+    release_coefficient: f32,
+    sample_rate: f32,
+}
+
+impl Signal for EnvelopeFollower {
+    fn tick(&mut self, input: f32) -> f32 {
+        let input = input.abs();
+        let coefficient = if input > self.envelope {
+            self.attack_coefficient
+        } else {
+            self.release_coefficient
+        };
+        self.envelope = input + coefficient * (self.envelope - input);
+        self.envelope
+    }
+
+    fn reset(&mut self) {
+        self.envelope = 0.0;
+    }
+}
+
+impl EnvelopeFollower {
+    /// Times in seconds
+    fn set_times(&mut self, attack: f32, release: f32) {
+        self.attack = attack;
+        self.release = release;
+
+        let coefficient = |time: f32| (-1.0 / (time * self.sample_rate)).exp();
+        self.attack_coefficient = coefficient(attack);
+        self.release_coefficient = coefficient(release);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.set_times(self.attack, self.release);
+    }
+}
+
+impl Default for EnvelopeFollower {
+    fn default() -> Self {
+        let mut follower = Self {
+            envelope: 0.0,
+            attack: 0.0,
+            release: 0.0,
+            attack_coefficient: 0.0,
+            release_coefficient: 0.0,
+            sample_rate: DEFAULT_SAMPLE_RATE as f32,
+        };
+        // Follows the input exactly until times are set
+        follower.set_times(0.0, 0.0);
+        follower
+    }
+}
+
+// Gate gain for a key signal. It opens as soon as the key reaches the threshold, stays open for the
+// hold time after the key last did, then closes over the release time on a raised cosine
+struct Gate {
+    // Linear gain
+    threshold: f32,
+    // In seconds
+    hold: f32,
+    release: f32,
+    sample_rate: f32,
+    hold_samples: usize,
+    release_step: f32,
+    // Samples since the key was last over the threshold
+    held: usize,
+    // Position in the release, fully open at 1
+    level: f32,
+}
+
+impl Signal for Gate {
+    fn tick(&mut self, input: f32) -> f32 {
+        if input.abs() >= self.threshold {
+            self.held = 0;
+            self.level = 1.0;
+        } else if self.held < self.hold_samples {
+            self.held += 1;
+        } else {
+            self.level = (self.level - self.release_step).max(0.0);
+        }
+
+        if self.level >= 1.0 {
+            1.0
+        } else {
+            0.5 - 0.5 * (PI * self.level).cos()
+        }
+    }
+
+    fn reset(&mut self) {
+        self.held = self.hold_samples;
+        self.level = 0.0;
+    }
+}
+
+impl Gate {
+    /// Threshold as a linear gain, hold and release in seconds
+    fn set(&mut self, threshold: f32, hold: f32, release: f32) {
+        self.threshold = threshold;
+        self.hold = hold;
+        self.release = release;
+        self.hold_samples = (hold * self.sample_rate).round() as usize;
+        self.release_step = 1.0 / (release * self.sample_rate).max(1.0);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.set(self.threshold, self.hold, self.release);
+    }
+}
+
+impl Default for Gate {
+    fn default() -> Self {
+        let mut gate = Self {
+            threshold: 0.0,
+            hold: 0.0,
+            release: 0.0,
+            sample_rate: DEFAULT_SAMPLE_RATE as f32,
+            hold_samples: 0,
+            release_step: 1.0,
+            held: 0,
+            level: 0.0,
+        };
+        gate.reset();
+        gate
+    }
+}
+
+// Plays a stereo signal back a window at a time, each window being the one before it reversed.
+// Short raised cosine fades at either end of a window keep the joins from clicking. The first
+// sample of a window comes back out as the last one of the next window, two windows less a sample
+// later
+struct Reverser {
+    buffer: Vec<[f32; 2]>,
+    write_index: usize,
+    // In seconds
+    time: f32,
+    sample_rate: f32,
+    // Window length in samples, and the length the next window starts with
+    length: usize,
+    next_length: usize,
+    // Samples into the current window
+    position: usize,
+    // Write index at the start of the current window
+    start: usize,
+    fade: usize,
+}
+
+impl MultiSignal<2> for Reverser {
+    fn tick(&mut self, input: [f32; 2]) -> [f32; 2] {
+        // Lengths only change between windows, so a window is never cut short
+        let len = self.buffer.len();
+        if self.position >= self.length {
+            self.position = 0;
+            self.start = self.write_index;
+
+            // A longer window would reach back past the one just written, into audio that has
+            // already been played, so it plays silence there instead
+            if self.next_length > self.length {
+                for ii in self.length + 1..=self.next_length {
+                    self.buffer[(self.start + len - ii) % len] = [0.0; 2];
+                }
+            }
+            self.length = self.next_length;
+        }
+
+        self.buffer[self.write_index] = input;
+        let output = self.buffer[(self.start + len - 1 - self.position) % len];
+
+        // Short windows are all fade
+        let fade = self.fade.min(self.length / 2);
+        let edge = self.position.min(self.length - 1 - self.position);
+        let gain = if edge < fade {
+            0.5 - 0.5 * (PI * (edge + 1) as f32 / (fade + 1) as f32).cos()
+        } else {
+            1.0
+        };
+
+        self.position += 1;
+        self.write_index += 1;
+        if self.write_index >= len {
+            self.write_index = 0;
+        }
+        output.map(|sample| sample * gain)
+    }
+
+    fn reset(&mut self) {
+        for frame in self.buffer.iter_mut() {
+            *frame = [0.0; 2];
+        }
+        self.position = 0;
+        self.start = self.write_index;
+        self.length = self.next_length;
+    }
+}
+
+impl Reverser {
+    fn new(time: f32, sample_rate: f32) -> Self {
+        let mut reverser = Self {
+            buffer: Vec::new(),
+            write_index: 0,
+            time,
+            sample_rate,
+            length: 1,
+            next_length: 1,
+            position: 0,
+            start: 0,
+            fade: 0,
+        };
+        reverser.set_sample_rate(sample_rate);
+        reverser
+    }
+
+    /// Window length in seconds, up to MAX_REVERSE. Takes effect from the next window
+    fn set_time(&mut self, time: f32) {
+        self.time = time;
+        self.next_length =
+            ((time * self.sample_rate).round() as usize).clamp(1, self.buffer.len() / 2);
+    }
+
+    /// Delay in samples from the start of a window to where it ends up in the output, for the
+    /// window being played
+    fn latency(&self) -> usize {
+        2 * self.length - 1
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        // Room for the window being written and the one being read back
+        let max_length = ((MAX_REVERSE * sample_rate) as usize).max(1);
+        self.buffer.resize(2 * max_length, [0.0; 2]);
+        self.write_index %= self.buffer.len();
+        self.fade = (REVERSE_FADE * sample_rate) as usize;
+        self.set_time(self.time);
+        self.reset();
+    }
+}
+
+/// Pitch of the shimmer
+#[derive(Enum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ShimmerInterval {
+    #[default]
+    #[name = "+12"]
+    OctaveUp,
+    #[name = "+7"]
+    Fifth,
+    #[name = "-12"]
+    OctaveDown,
+}
+
+impl ShimmerInterval {
+    fn ratio(self) -> f32 {
+        let semitones = match self {
+            ShimmerInterval::OctaveUp => 12.0,
+            ShimmerInterval::Fifth => 7.0,
+            ShimmerInterval::OctaveDown => -12.0,
+        };
+        2f32.powf(semitones / 12.0)
+    }
+}
+
+// Delay line pitch shifter for a number of channels. Two read heads sweep through a window at the
+// rate that shifts the pitch, half a window apart, and each fades out before it jumps back. The
+// fades add up to one so it never gets louder than its input. All channels share the heads
+// https://ccrma.stanford.edu/~jos/pasp/Time_Varying_Delay_Effects.html
+struct PitchShifter<const CHANNELS: usize> {
+    buffer: Vec<[f32; CHANNELS]>,
+    write_index: usize,
+    // Window length in samples
+    window: f32,
+    // Position of the first head in the window, between 0 and 1
+    phase: f32,
+    ratio: f32,
+}
+
+impl<const CHANNELS: usize> PitchShifter<CHANNELS> {
+    fn new(ratio: f32, sample_rate: f32) -> Self {
+        let mut shifter = Self {
+            buffer: Vec::new(),
+            write_index: 0,
+            window: 1.0,
+            phase: 0.0,
+            ratio,
+        };
+        shifter.set_sample_rate(sample_rate);
+        shifter
+    }
+
+    /// Shift the first `values.len()` channels, in place
+    fn process(&mut self, values: &mut [f32]) {
+        self.buffer[self.write_index][..values.len()].copy_from_slice(values);
+
+        // Frames either side of a head and how far it is between them
+        let len = self.buffer.len();
+        let head = |delay: f32| {
+            let index = delay.floor();
+            let frac = delay - index;
+            let index = self.write_index + len - index as usize;
+            (index % len, (index + len - 1) % len, frac)
+        };
+
+        let other = (self.phase + 0.5).fract();
+        let fade = (PI * self.phase).sin().powi(2);
+        let (a0, a1, a_frac) = head(self.phase * self.window);
+        let (b0, b1, b_frac) = head(other * self.window);
+
+        for (ch, value) in values.iter_mut().enumerate() {
+            let a = self.buffer[a0][ch] + a_frac * (self.buffer[a1][ch] - self.buffer[a0][ch]);
+            let b = self.buffer[b0][ch] + b_frac * (self.buffer[b1][ch] - self.buffer[b0][ch]);
+            *value = a * fade + b * (1.0 - fade);
+        }
+
+        // A head that reads ever closer to the write index raises the pitch
+        self.phase = (self.phase + (1.0 - self.ratio) / self.window).rem_euclid(1.0);
+
+        self.write_index += 1;
+        if self.write_index >= len {
+            self.write_index = 0;
+        }
+    }
+
+    fn reset(&mut self) {
+        for frame in self.buffer.iter_mut() {
+            *frame = [0.0; CHANNELS];
+        }
+        self.phase = 0.0;
+    }
+
+    /// Output frequency over input frequency
+    fn set_ratio(&mut self, ratio: f32) {
+        self.ratio = ratio;
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.window = SHIMMER_WINDOW * sample_rate;
+        self.buffer
+            .resize(self.window as usize + 2, [0.0; CHANNELS]);
+        self.write_index %= self.buffer.len();
+    }
+}
+
+// Pitch shifts part of the FDN feedback so the tail climbs with every trip around the loop. Each
+// line crossfades its feedback with a shifted copy, so the amount is the portion of the feedback
+// that changes pitch. The shifter never gets louder than its input, so neither does the loop
+struct Shimmer<const SIZE: usize> {
+    shifter: PitchShifter<SIZE>,
+    amount: f32,
+}
+
+impl<const SIZE: usize> Default for Shimmer<SIZE> {
+    fn default() -> Self {
+        Self {
+            shifter: PitchShifter::new(
+                ShimmerInterval::default().ratio(),
+                DEFAULT_SAMPLE_RATE as f32,
+            ),
+            amount: 0.0,
+        }
+    }
+}
+
+impl<const SIZE: usize> Shimmer<SIZE> {
+    /// Process the feedback of the lines in use, in place
+    fn process(&mut self, values: &mut [f32]) {
+        if self.amount <= 0.0 {
+            return;
+        }
+
+        let mut dry = [0.0; SIZE];
+        dry[..values.len()].copy_from_slice(values);
+        self.shifter.process(values);
+        for (value, dry) in values.iter_mut().zip(dry) {
+            *value = dry + (*value - dry) * self.amount;
+        }
+    }
+
+    fn set_amount(&mut self, amount: f32) {
+        // The shifter stops while off, don't play out what it held from back then
+        if self.amount <= 0.0 && amount > 0.0 {
+            self.shifter.reset();
+        }
+        self.amount = amount;
+    }
+}
+
+// Frequency dependent loss for one delay line. A highpass and the damping lowpass are followed by
+// a low and a high shelf around the mid band gain, so each band can decay at its own rate
+// https://ccrma.stanford.edu/~jos/pasp/Achieving_Desired_Reverberation_Times.html
+#[derive(Clone, Copy)]
+struct Absorption {
+    // Highpass made by taking the lowpass away from the input, passes everything at 0 Hz
+    low_cut: FirstOrderLowpass,
+    damping: OnePole,
+    low: FirstOrderLowpass,
+    high: FirstOrderLowpass,
+    gain: f32,
+    low_ratio: f32,
+    high_ratio: f32,
+    // How far the output is pulled back to the unfiltered input, the loop is lossless at 1
+    freeze: f32,
+}
+
+impl Signal for Absorption {
+    fn tick(&mut self, input: f32) -> f32 {
+        let mut output = input - self.low_cut.tick(input);
+        output = self.damping.tick(output);
+
+        // First order shelves, their response moves monotonically between the two band gains so
+        // the loop never gains more than its loudest band
+        output += (self.low_ratio - 1.0) * self.low.tick(output);
+        output += (self.high_ratio - 1.0) * (output - self.high.tick(output));
+
+        // The filters keep running while frozen so letting go doesn't click
+        output * self.gain * (1.0 - self.freeze) + input * self.freeze
+    }
+
+    fn reset(&mut self) {
+        self.low_cut.reset();
+        self.damping.reset();
+        self.low.reset();
+        self.high.reset();
+    }
+}
+
+impl Absorption {
+    /// Set the gain of the low, mid and high band
+    fn set_gains(&mut self, low: f32, mid: f32, high: f32) {
+        self.gain = mid;
+
+        if mid > 0.0 {
+            self.low_ratio = low / mid;
+            self.high_ratio = high / mid;
+        } else {
+            self.low_ratio = 1.0;
+            self.high_ratio = 1.0;
+        }
+    }
+
+    fn set_crossovers(&mut self, low: f32, high: f32) {
+        self.low.set_cutoff(low);
+        self.high.set_cutoff(high);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.low_cut.set_sample_rate(sample_rate);
+        self.damping.set_sample_rate(sample_rate);
+        self.low.set_sample_rate(sample_rate);
+        self.high.set_sample_rate(sample_rate);
+    }
+}
+
+impl Default for Absorption {
+    fn default() -> Self {
+        let mut low_cut = FirstOrderLowpass::default();
+        low_cut.set_cutoff(0.0);
+
+        Self {
+            low_cut,
+            damping: OnePole::default(),
+            low: FirstOrderLowpass::default(),
+            high: FirstOrderLowpass::default(),
+            gain: 1.0,
+            low_ratio: 1.0,
+            high_ratio: 1.0,
+            freeze: 0.0,
+        }
+    }
+}
+
+/// Mixes the delay line outputs back into their inputs, in place. Every matrix here is orthogonal,
+/// so the feedback is lossless and all of the decay comes from the absorption filters. The
+/// Hadamard and random matrices need a power of two lines
+/// https://ccrma.stanford.edu/~jos/pasp/Choice_Lossless_Feedback_Matrix.html
+trait FeedbackMatrix {
+    fn mix(&mut self, values: &mut [f32]);
+
+    /// How many samples a change of matrix takes to crossfade
+    fn set_crossfade(&mut self, _samples: usize) {}
+
+    /// Whether a network with this matrix puts out the mixed lines instead of the lines as they
+    /// come out of the delays
+    fn output_after_mix(&self) -> bool {
+        false
+    }
+
+    /// Morph from a diagonal matrix at 0, where each line only feeds itself like a bank of
+    /// parallel combs, to the full matrix at 1. The matrix stays orthogonal all the way
+    fn set_density(&mut self, _density: f32) {}
+}
+
+/// All outputs are summed and fed back into all inputs. Below full density the reflection axis
+/// turns towards the first line, so at 0 only that line is inverted. A reflection can't turn into
+/// the identity without a jump, but a bank of combs with one of them inverted is still a bank of
+/// parallel combs
+/// https://github.com/madronalabs/madronalib/blob/master/source/DSP/MLDSPFilters.h#L953
+/// https://ccrma.stanford.edu/~jos/pasp/Householder_Feedback_Matrix.html
+struct Householder {
+    density: f32,
+}
+
+impl Default for Householder {
+    fn default() -> Self {
+        Self { density: 1.0 }
+    }
+}
+
+impl FeedbackMatrix for Householder {
+    fn mix(&mut self, values: &mut [f32]) {
+        let lines = values.len() as f32;
+
+        if self.density >= 1.0 {
+            let sum: f32 = values.iter().sum();
+            let delay_sum = sum * 2.0 / lines;
+            for value in values.iter_mut() {
+                *value -= delay_sum;
+            }
+            return;
+        }
+
+        let sum: f32 = values.iter().sum();
+
+        // Turn the reflection axis from the first line to the uniform axis
+        let uniform = (1.0 / lines).sqrt();
+        let first = 1.0 - self.density + self.density * uniform;
+        let rest = self.density * uniform;
+        let norm = (first * first + rest * rest * (lines - 1.0)).sqrt();
+        let (first, rest) = (first / norm, rest / norm);
+
+        let projection = 2.0 * (first * values[0] + rest * (sum - values[0]));
+        values[0] += (rest - first) * projection;
+        for value in values.iter_mut() {
+            *value -= rest * projection;
+        }
+    }
+
+    fn set_density(&mut self, density: f32) {
+        self.density = density.clamp(0.0, 1.0);
+    }
+}
+
+/// https://ccrma.stanford.edu/~jos/pasp/Hadamard_Matrix.html
+/// https://github.com/SamiPerttu/fundsp/blob/50811676691a3d066964241e344987d4c45c3e9d/src/feedback.rs#L9
+struct Hadamard {
+    // Butterfly coefficients, only normalized at the end for the full matrix
+    c: f32,
+    s: f32,
+    normalize: bool,
+    // Rotation of the lines that end up inverted, below full density
+    pair_c: f32,
+    pair_s: f32,
+}
+
+impl Default for Hadamard {
+    fn default() -> Self {
+        Self {
+            c: 1.0,
+            s: 1.0,
+            normalize: true,
+            pair_c: -1.0,
+            pair_s: 0.0,
+        }
+    }
+}
+
+impl FeedbackMatrix for Hadamard {
+    // The Hadamard network has always been read after the matrix
+    fn output_after_mix(&self) -> bool {
+        true
+    }
+
+    fn mix(&mut self, values: &mut [f32]) {
+        let lines = values.len();
+
+        if !self.normalize {
+            let mut h = 1;
+            while h < lines {
+                let mut i = 0;
+                while i < lines {
+                    for j in i..i + h {
+                        let x = values[j];
+                        let y = values[j + h];
+                        values[j] = self.c * x + self.s * y;
+                        values[j + h] = self.c * y - self.s * x;
+                    }
+                    i += h * 2;
+                }
+                h *= 2;
+            }
+
+            // The sum and difference butterflies are these rotations with every line that has an
+            // odd number of bits inverted. Turning those lines in pairs by up to half a turn gets
+            // there without a jump. With two lines the odd one out flips at full density
+            let mut odd = (0..lines).filter(|ii| ii.count_ones() % 2 == 1);
+            while let (Some(p), Some(q)) = (odd.next(), odd.next()) {
+                let (x, y) = (values[p], values[q]);
+                values[p] = self.pair_c * x + self.pair_s * y;
+                values[q] = self.pair_c * y - self.pair_s * x;
+            }
+            return;
+        }
+
+        let mut h = 1;
+        while h < lines {
+            let mut i = 0;
+            while i < lines {
+                for j in i..i + h {
+                    let x = values[j];
+                    let y = values[j + h];
+                    values[j] = self.c * x + self.s * y;
+                    values[j + h] = self.s * x - self.c * y;
+                }
+                i += h * 2;
+            }
+            h *= 2;
+        }
+
+        // Normalization for up to 511 channels.
+        let mut c = 1.0;
+        if lines >= 256 {
+            c = 1.0 / 16.0;
+        } else if lines >= 128 {
+            c = 1.0 / (SQRT_2 * 8.0);
+        } else if lines >= 64 {
+            c = 1.0 / 8.0;
+        } else if lines >= 32 {
+            c = 1.0 / (SQRT_2 * 4.0);
+        } else if lines >= 16 {
+            c = 1.0 / 4.0;
+        } else if lines >= 8 {
+            c = 1.0 / (SQRT_2 * 2.0);
+        } else if lines >= 4 {
+            c = 1.0 / 2.0;
+        } else if lines >= 2 {
+            c = 1.0 / SQRT_2;
+        }
+
+        for value in values.iter_mut() {
+            *value *= c;
+        }
+    }
+
+    fn set_density(&mut self, density: f32) {
+        if density >= 1.0 {
+            *self = Self::default();
+            return;
+        }
+
+        // Each butterfly is a rotation, from leaving the pair alone at 0 to the normalized sum
+        // and difference at 1, a Hadamard matrix with some lines inverted
+        let density = density.max(0.0);
+        let angle = density * PI / 4.0;
+        self.c = angle.cos();
+        self.s = angle.sin();
+        self.normalize = false;
+        let pair_angle = density * PI;
+        self.pair_c = pair_angle.cos();
+        self.pair_s = pair_angle.sin();
+    }
+}
+
+/// Seeded random orthogonal matrix for up to `SIZE` lines. Uses the same butterflies as the
+/// Hadamard matrix, but each one rotates its pair of lines by a random angle instead of a quarter
+/// turn
+struct RandomOrthogonal<const SIZE: usize> {
+    angles: Vec<f32>,
+    // Cosine and sine of each butterfly, stage by stage
+    butterflies: Vec<(f32, f32)>,
+    density: f32,
+}
+
+impl<const SIZE: usize> RandomOrthogonal<SIZE> {
+    fn new(seed: u32) -> Self {
+        let stages = SIZE.next_power_of_two().trailing_zeros() as usize;
+        let mut matrix = Self {
+            angles: vec![0.0; stages * SIZE / 2],
+            butterflies: vec![(1.0, 0.0); stages * SIZE / 2],
+            density: 1.0,
+        };
+        matrix.set_seed(seed);
+        matrix
+    }
+
+    fn set_seed(&mut self, seed: u32) {
+        let mut random = Random::new(seed);
+        for angle in self.angles.iter_mut() {
+            // Stay away from angles that barely mix the pair
+            *angle = PI / 4.0 + random.next_bipolar() * PI / 8.0;
+        }
+        self.update_butterflies();
+    }
+
+    fn update_butterflies(&mut self) {
+        for (butterfly, angle) in self.butterflies.iter_mut().zip(self.angles.iter()) {
+            let angle = angle * self.density;
+            *butterfly = (angle.cos(), angle.sin());
+        }
+    }
+}
+
+impl<const SIZE: usize> Default for RandomOrthogonal<SIZE> {
+    fn default() -> Self {
+        Self::new(DEFAULT_SEED)
+    }
+}
+
+impl<const SIZE: usize> FeedbackMatrix for RandomOrthogonal<SIZE> {
+    fn mix(&mut self, values: &mut [f32]) {
+        let lines = values.len();
+        let mut butterflies = self.butterflies.iter();
+
+        let mut h = 1;
+        while h < lines {
+            let mut i = 0;
+            while i < lines {
+                for j in i..i + h {
+                    let (c, s) = butterflies.next().copied().unwrap_or((1.0, 0.0));
+                    let x = values[j];
+                    let y = values[j + h];
+                    values[j] = c * x + s * y;
+                    values[j + h] = c * y - s * x;
+                }
+                i += h * 2;
+            }
+            h *= 2;
         }
     }
 
-    fn set_gain(&mut self, gain: f32) -> () {
-        self.gain = gain;
+    fn set_density(&mut self, density: f32) {
+        let density = density.clamp(0.0, 1.0);
+        if density != self.density {
+            self.density = density;
+            self.update_butterflies();
+        }
     }
 }
 
-#[derive(Clone, Copy)]
-struct OnePole {
-    y1: f32,
-    a0: f32,
-    b1: f32,
+/// No mixing, every line feeds back into itself like a bank of parallel combs
+#[derive(Default)]
+struct Identity;
+
+impl FeedbackMatrix for Identity {
+    fn mix(&mut self, _values: &mut [f32]) {}
 }
 
-// // A one pole filter, https://ccrma.stanford.edu/~jos/fp/One_Pole.html
-impl Signal for OnePole {
-    fn tick(&mut self, input: f32) -> f32 {
-        self.y1 = input * self.a0 + self.y1 * self.b1;
-        self.y1
-    }
+#[derive(Enum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Matrix {
+    #[default]
+    Householder,
+    Hadamard,
+    Random,
+    #[name = "Parallel Combs"]
+    Identity,
+}
 
-    fn reset(&mut self) -> () {
-        self.y1 = 0.0;
-    }
+/// Any of the matrices above, chosen at runtime. A change crossfades from the old mix to the new
+/// one, and a change during a fade waits for it to finish
+struct SwitchableMatrix<const SIZE: usize> {
+    householder: Householder,
+    hadamard: Hadamard,
+    random: RandomOrthogonal<SIZE>,
+    identity: Identity,
+    previous: Matrix,
+    current: Matrix,
+    target: Matrix,
+    fade: f32,
+    fade_step: f32,
 }
 
-impl OnePole {
-    fn new(cutoff: f32) -> Self {
-        let mut filter = Self::default();
-        filter.set_cutoff(cutoff);
-        filter
+impl<const SIZE: usize> SwitchableMatrix<SIZE> {
+    fn set_matrix(&mut self, matrix: Matrix) {
+        self.target = matrix;
+        if self.fade >= 1.0 {
+            self.start_fade();
+        }
     }
 
-    fn set_cutoff(&mut self, cutoff: f32) -> () {
-        let x = (-TAU * cutoff).exp();
-        self.a0 = 1.0 - x;
-        self.b1 = x;
+    fn start_fade(&mut self) {
+        if self.target == self.current {
+            return;
+        }
+
+        self.previous = self.current;
+        self.current = self.target;
+        self.fade = if self.fade_step >= 1.0 { 1.0 } else { 0.0 };
+    }
+
+    fn mix_with(&mut self, matrix: Matrix, values: &mut [f32]) {
+        match matrix {
+            Matrix::Householder => self.householder.mix(values),
+            Matrix::Hadamard => self.hadamard.mix(values),
+            Matrix::Random => self.random.mix(values),
+            Matrix::Identity => self.identity.mix(values),
+        }
     }
 }
 
-impl Default for OnePole {
+impl<const SIZE: usize> Default for SwitchableMatrix<SIZE> {
     fn default() -> Self {
         Self {
-            y1: 0.0,
-            a0: 1f32,
-            b1: 0.0,
+            householder: Householder::default(),
+            hadamard: Hadamard::default(),
+            random: RandomOrthogonal::default(),
+            identity: Identity,
+            previous: Matrix::default(),
+            current: Matrix::default(),
+            target: Matrix::default(),
+            fade: 1.0,
+            fade_step: 1.0,
+        }
+    }
+}
+
+impl<const SIZE: usize> FeedbackMatrix for SwitchableMatrix<SIZE> {
+    fn mix(&mut self, values: &mut [f32]) {
+        if self.fade >= 1.0 {
+            self.mix_with(self.current, values);
+            return;
+        }
+
+        // The old matrix's mix
+        let mut previous = [0.0; SIZE];
+        let previous = &mut previous[..values.len()];
+        previous.copy_from_slice(values);
+        self.mix_with(self.previous, previous);
+        self.mix_with(self.current, values);
+
+        // Blending two orthogonal matrices can only lose energy, so the tank stays stable
+        for (value, previous) in values.iter_mut().zip(previous.iter()) {
+            *value = previous + (*value - previous) * self.fade;
+        }
+
+        self.fade += self.fade_step;
+        if self.fade >= 1.0 {
+            self.fade = 1.0;
+            self.start_fade();
         }
     }
+
+    fn set_crossfade(&mut self, samples: usize) {
+        self.fade_step = 1.0 / samples.max(1) as f32;
+    }
+
+    fn set_density(&mut self, density: f32) {
+        self.householder.set_density(density);
+        self.hadamard.set_density(density);
+        self.random.set_density(density);
+    }
 }
 
-struct HouseholderFDN<const SIZE: usize> {
-    delays: [IntegerDelay; SIZE],
-    filters: [OnePole; SIZE],
+/// Up to `SIZE` delay lines, of which the first `order` run
+struct FeedbackDelayNetwork<const SIZE: usize, M: FeedbackMatrix> {
+    order: usize,
+    delays: [FractionalDelay; SIZE],
+    filters: [Absorption; SIZE],
+    modulators: [Lfo; SIZE],
+    matrix: M,
+    depth: f32,
+    // Between 0 and 1, the modulation fades out with it
+    freeze: f32,
     values: [f32; SIZE],
-    gain: f32,
+    decay: Option<f32>,
+    low_multiplier: f32,
+    high_multiplier: f32,
+    shimmer: Shimmer<SIZE>,
 }
 
-impl<const SIZE: usize> HouseholderFDN<SIZE> {
-    fn new(delays: [usize; SIZE], gain: f32, max_delay: usize) -> Self {
-        let delays = delays.map(|delay| IntegerDelay::new(max_delay, delay));
+// The fixed matrix networks the tests drive directly, the reverb switches matrices at runtime
+#[cfg(test)]
+type HouseholderFDN<const SIZE: usize> = FeedbackDelayNetwork<SIZE, Householder>;
+#[cfg(test)]
+type HadamardFDN<const SIZE: usize> = FeedbackDelayNetwork<SIZE, Hadamard>;
 
-        Self {
+impl<const SIZE: usize, M: FeedbackMatrix + Default> FeedbackDelayNetwork<SIZE, M> {
+    fn new(delays: [f32; SIZE], gain: f32, max_delay: usize) -> Self {
+        let delays = delays.map(|delay| FractionalDelay::new(max_delay, delay));
+
+        // Spread the phases so the lines don't move together. Bit reversed like the delay lengths,
+        // so the lines used at every power of two order still cover the whole cycle
+        let modulators = std::array::from_fn(|ii: usize| {
+            let phase = ii.reverse_bits() as f32 / 2f32.powi(usize::BITS as i32);
+            Lfo::new(phase, ii as u32 + 1)
+        });
+
+        let mut fdn = Self {
+            order: SIZE,
             delays: delays,
-            filters: [OnePole::default(); SIZE],
-            gain: gain,
+            filters: [Absorption::default(); SIZE],
+            modulators,
+            matrix: M::default(),
+            depth: 0.0,
+            freeze: 0.0,
+            decay: None,
+            low_multiplier: 1.0,
+            high_multiplier: 1.0,
             values: [0.0; SIZE],
+            shimmer: Shimmer::default(),
+        };
+        fdn.set_gain(gain);
+        fdn
+    }
+}
+
+impl<const SIZE: usize, M: FeedbackMatrix> FeedbackDelayNetwork<SIZE, M> {
+    /// Number of lines to run, a power of two for the Hadamard and random matrices. Lines that
+    /// come back start out silent, without clearing their buffers on the audio thread
+    fn set_order(&mut self, order: usize) {
+        let order = order.clamp(1, SIZE);
+        for ii in self.order..order {
+            self.delays[ii].empty();
+            self.filters[ii].reset();
+            self.values[ii] = 0.0;
         }
+        self.order = order;
     }
 
+    /// The same loop gain for every line, regardless of its length
     fn set_gain(&mut self, gain: f32) -> () {
-        self.gain = gain;
+        self.decay = None;
+        for filter in self.filters.iter_mut() {
+            filter.set_gains(gain, gain, gain);
+        }
     }
 
-    fn set_delays(&mut self, delays: [usize; SIZE]) -> () {
-        for (ii, delay) in delays.iter().enumerate() {
-            self.delays[ii].set_delay(*delay);
-        }
+    /// Give every line the loop gain that makes it fall 60 dB in `decay` samples
+    #[cfg(test)]
+    fn set_decay(&mut self, decay: f32) {
+        self.decay = Some(decay);
+        self.update_gains();
     }
 
-    fn set_max_delays(&mut self, max_delay: usize) -> () {
-        for delay in self.delays.iter_mut() {
-            delay.set_max_delay(max_delay);
-        }
+    /// Scale the decay time of the bands below and above the crossovers
+    #[cfg(test)]
+    fn set_decay_multipliers(&mut self, low: f32, high: f32) {
+        self.low_multiplier = low;
+        self.high_multiplier = high;
+        self.update_gains();
     }
 
-    fn set_cutoff(&mut self, cutoff: f32) -> () {
+    /// Set the lengths, the decay in samples and its multipliers together, working out the loop
+    /// gains only once
+    fn set_tuning(&mut self, delays: [f32; SIZE], decay: f32, low: f32, high: f32) {
+        self.decay = Some(decay);
+        self.low_multiplier = low;
+        self.high_multiplier = high;
+        self.set_delays(delays);
+    }
+
+    /// Crossover frequencies in Hz between the low, mid and high band
+    fn set_crossovers(&mut self, low: f32, high: f32) {
         for filter in self.filters.iter_mut() {
-            filter.set_cutoff(cutoff);
+            filter.set_crossovers(low, high);
         }
     }
-}
-
-impl<const CHANNELS: usize> MultiSignal<CHANNELS> for HouseholderFDN<CHANNELS> {
-    fn tick(&mut self, input: [f32; CHANNELS]) -> [f32; CHANNELS] {
-        let mut output = input;
 
-        // Run the delay lines
-        for (ii, sample) in output.iter_mut().enumerate() {
-            let input = *sample + self.values[ii];
-            *sample = self.filters[ii].tick(self.delays[ii].tick(input)) * self.gain;
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        for filter in self.filters.iter_mut() {
+            filter.set_sample_rate(sample_rate);
         }
+        self.shimmer.shifter.set_sample_rate(sample_rate);
+    }
 
-        // Householder feedback matrix. All outputs are summed and fed back into all inputs
-        // https://github.com/madronalabs/madronalib/blob/master/source/DSP/MLDSPFilters.h#L953
-        // https://ccrma.stanford.edu/~jos/pasp/Householder_Feedback_Matrix.html
-        let mut delay_sum: f32 = output.iter().sum();
-        delay_sum *= 2.0 / CHANNELS as f32;
+    fn update_gains(&mut self) {
+        if let Some(decay) = self.decay {
+            let decay = decay.max(1.0);
+            let band_gain = |length: f32, decay: f32| 0.001f32.powf(length / decay);
+
+            // https://ccrma.stanford.edu/~jos/pasp/Choice_Delay_Line_Lengths.html
+            for (filter, delay) in self.filters.iter_mut().zip(self.delays.iter()) {
+                let length = delay.delay();
+                filter.set_gains(
+                    band_gain(length, decay * self.low_multiplier),
+                    band_gain(length, decay),
+                    band_gain(length, decay * self.high_multiplier),
+                );
+            }
+        }
+    }
 
-        // Set the feedback, all delays are fed back into each other
-        for (ii, value) in self.values.iter_mut().enumerate() {
-            *value = output[ii] - delay_sum;
+    fn set_delays(&mut self, delays: [f32; SIZE]) -> () {
+        for (ii, delay) in delays.iter().enumerate() {
+            self.delays[ii].set_delay(*delay);
         }
 
-        output
+        // Longer lines lose more per trip to keep the decay time the same
+        self.update_gains();
     }
 
-    fn reset(&mut self) -> () {
-        for filter in self.filters.iter_mut() {
-            filter.reset();
-        }
+    fn set_interpolation(&mut self, interpolation: Interpolation) {
         for delay in self.delays.iter_mut() {
-            delay.reset();
-        }
-        for value in self.values.iter_mut() {
-            *value = 0.0;
+            delay.set_interpolation(interpolation);
         }
     }
-}
-
-struct HadamardFDN<const SIZE: usize> {
-    delays: [IntegerDelay; SIZE],
-    filters: [OnePole; SIZE],
-    values: [f32; SIZE],
-    gain: f32,
-}
 
-impl<const SIZE: usize> HadamardFDN<SIZE> {
-    fn new(delays: [usize; SIZE], gain: f32, max_delay: usize) -> Self {
-        let delays = delays.map(|delay| IntegerDelay::new(max_delay, delay));
-
-        Self {
-            delays: delays,
-            filters: [OnePole::default(); SIZE],
-            gain: gain,
-            values: [0.0; SIZE],
+    fn set_crossfade(&mut self, samples: usize) {
+        for delay in self.delays.iter_mut() {
+            delay.set_crossfade(samples);
         }
+        self.matrix.set_crossfade(samples);
     }
 
-    fn set_gain(&mut self, gain: f32) -> () {
-        self.gain = gain;
+    /// How much the lines mix into each other, from parallel combs at 0 to the full matrix at 1
+    fn set_density(&mut self, density: f32) {
+        self.matrix.set_density(density);
     }
 
-    fn set_delays(&mut self, delays: [usize; SIZE]) -> () {
-        for (ii, delay) in delays.iter().enumerate() {
-            self.delays[ii].set_delay(*delay);
+    /// Rate in cycles per sample, depth in samples
+    fn set_modulation(&mut self, rate: f32, depth: f32) {
+        for modulator in self.modulators.iter_mut() {
+            modulator.set_rate(rate);
+        }
+        self.depth = depth;
+    }
+
+    fn set_modulation_shape(&mut self, shape: LfoShape) {
+        for modulator in self.modulators.iter_mut() {
+            modulator.set_shape(shape);
         }
     }
 
@@ -421,67 +2919,75 @@ impl<const SIZE: usize> HadamardFDN<SIZE> {
         }
     }
 
+    /// Damping cutoff in Hz
     fn set_cutoff(&mut self, cutoff: f32) -> () {
         for filter in self.filters.iter_mut() {
-            filter.set_cutoff(cutoff);
+            filter.damping.set_cutoff(cutoff);
+        }
+    }
+
+    /// Highpass cutoff in Hz. It never goes below DC_BLOCKER_CUTOFF, so DC and sub-bass can't
+    /// build up in the loop however long the decay
+    fn set_low_cut(&mut self, cutoff: f32) {
+        for filter in self.filters.iter_mut() {
+            filter.low_cut.set_cutoff(cutoff.max(DC_BLOCKER_CUTOFF));
+        }
+    }
+
+    /// How much of the feedback goes through the pitch shifter, between 0 and 1
+    fn set_shimmer(&mut self, amount: f32, interval: ShimmerInterval) {
+        self.shimmer.set_amount(amount);
+        self.shimmer.shifter.set_ratio(interval.ratio());
+    }
+
+    /// Bypass the loss in every line and stop the modulation, fully at 1. Moving read heads
+    /// interpolate, which loses some of the tail on every trip. With a lossless matrix the tail
+    /// then holds
+    fn set_freeze(&mut self, amount: f32) {
+        self.freeze = amount;
+        for filter in self.filters.iter_mut() {
+            filter.freeze = amount;
         }
     }
 }
 
-impl<const CHANNELS: usize> MultiSignal<CHANNELS> for HadamardFDN<CHANNELS> {
+impl<const SIZE: usize> FeedbackDelayNetwork<SIZE, SwitchableMatrix<SIZE>> {
+    fn set_matrix(&mut self, matrix: Matrix) {
+        self.matrix.set_matrix(matrix);
+    }
+
+    /// Pick the random orthogonal matrix. It changes straight away, so this can click while the
+    /// random matrix is in use
+    fn set_matrix_seed(&mut self, seed: u32) {
+        self.matrix.random.set_seed(seed);
+    }
+}
+
+impl<const CHANNELS: usize, M: FeedbackMatrix> MultiSignal<CHANNELS>
+    for FeedbackDelayNetwork<CHANNELS, M>
+{
     fn tick(&mut self, input: [f32; CHANNELS]) -> [f32; CHANNELS] {
         let mut output = input;
 
-        // Run the delay lines
-        for (ii, sample) in output.iter_mut().enumerate() {
-            let input = *sample + self.values[ii];
-            *sample = self.filters[ii].tick(self.delays[ii].tick(input)) * self.gain;
-        }
+        // Run the delay lines, held still while frozen
+        let depth = self.depth * (1.0 - self.freeze);
+        for (ii, sample) in output.iter_mut().enumerate().take(self.order) {
+            self.delays[ii].set_modulation(self.modulators[ii].tick() * depth);
 
-        // Hadamard feedback matrix
-        // https://ccrma.stanford.edu/~jos/pasp/Hadamard_Matrix.html
-        // https://github.com/SamiPerttu/fundsp/blob/50811676691a3d066964241e344987d4c45c3e9d/src/feedback.rs#L9
-        let mut h = 1;
-        while h < CHANNELS {
-            let mut i = 0;
-            while i < CHANNELS {
-                for j in i..i + h {
-                    let x = output[j];
-                    let y = output[j + h];
-                    output[j] = x + y;
-                    output[j + h] = x - y;
-                }
-                i += h * 2;
-            }
-            h *= 2;
+            let input = *sample + self.values[ii];
+            *sample = self.filters[ii].tick(self.delays[ii].tick(input));
         }
-
-        // Normalization for up to 511 channels.
-        let mut c = 1.0;
-        if CHANNELS >= 256 {
-            c = 1.0 / 16.0;
-        } else if CHANNELS >= 128 {
-            c = 1.0 / (SQRT_2 * 8.0);
-        } else if CHANNELS >= 64 {
-            c = 1.0 / 8.0;
-        } else if CHANNELS >= 32 {
-            c = 1.0 / (SQRT_2 * 4.0);
-        } else if CHANNELS >= 16 {
-            c = 1.0 / 4.0;
-        } else if CHANNELS >= 8 {
-            c = 1.0 / (SQRT_2 * 2.0);
-        } else if CHANNELS >= 4 {
-            c = 1.0 / 2.0;
-        } else if CHANNELS >= 2 {
-            c = 1.0 / SQRT_2;
+        for sample in output[self.order..].iter_mut() {
+            *sample = 0.0;
         }
 
-        output = output.map(|x| x * c);
-
-        // Set the feedback
-        for (ii, value) in self.values.iter_mut().enumerate() {
-            *value = output[ii];
+        // Set the feedback, the delays are mixed into each other
+        self.values = output;
+        self.matrix.mix(&mut self.values[..self.order]);
+        if self.matrix.output_after_mix() {
+            output = self.values;
         }
+        self.shimmer.process(&mut self.values[..self.order]);
 
         output
     }
@@ -496,6 +3002,7 @@ impl<const CHANNELS: usize> MultiSignal<CHANNELS> for HadamardFDN<CHANNELS> {
         for value in self.values.iter_mut() {
             *value = 0.0;
         }
+        self.shimmer.shifter.reset();
     }
 }
 
@@ -509,7 +3016,7 @@ mod tests {
 
     #[test]
     fn test_delay() {
-        let mut delay = IntegerDelay::new(10, 10);
+        let mut delay = FractionalDelay::new(10, 10.0);
 
         assert_eq!(delay.tick(1.0), 0.0);
 
@@ -528,19 +3035,19 @@ mod tests {
 
     #[test]
     fn test_delay_entire_buffer() {
-        let mut delay = IntegerDelay::new(10, 1);
+        let mut delay = FractionalDelay::new(10, 1.0);
 
-        for i in 0..10 {
+        for i in 0..20 {
             delay.tick(i as f32);
         }
 
-        assert_eq!(delay.tick(1.0), 9.0);
+        assert_eq!(delay.tick(1.0), 19.0);
         assert_eq!(delay.tick(1.0), 1.0);
     }
 
     #[test]
     fn test_change_delay() {
-        let mut delay = IntegerDelay::new(10, 1);
+        let mut delay = FractionalDelay::new(10, 1.0);
 
         for i in 0..10 {
             delay.tick(i as f32);
@@ -548,29 +3055,363 @@ mod tests {
 
         assert_eq!(delay.tick(1.0), 9.0);
 
-        delay.set_delay(2);
-        assert_eq!(delay.tick(0.5), 1.0);
-        assert_eq!(delay.tick(0.25), 0.0);
+        // Without a crossfade the new length reads straight away
+        delay.set_delay(2.0);
+        assert_eq!(delay.tick(0.5), 9.0);
+        assert_eq!(delay.tick(0.25), 1.0);
         assert_eq!(delay.tick(0.1), 0.5);
         assert_eq!(delay.tick(0.01), 0.25);
     }
 
+    #[test]
+    fn test_fractional_delay_whole_samples() {
+        for interpolation in [
+            Interpolation::Linear,
+            Interpolation::Cubic,
+            Interpolation::Thiran,
+        ] {
+            let mut delay = FractionalDelay::new(10, 3.0);
+            delay.set_interpolation(interpolation);
+
+            let output: Vec<f32> = (1..8).map(|i| delay.tick(i as f32)).collect();
+
+            assert_eq!(output, [0.0, 0.0, 0.0, 1.0, 2.0, 3.0, 4.0]);
+        }
+    }
+
+    #[test]
+    fn test_fractional_delay_linear() {
+        let mut delay = FractionalDelay::new(10, 2.5);
+        delay.set_interpolation(Interpolation::Linear);
+
+        for i in 0..5 {
+            delay.tick(i as f32);
+        }
+
+        // Halfway between the samples written 2 and 3 ticks before
+        assert_eq!(delay.tick(5.0), 2.5);
+
+        delay.set_delay(1.25);
+        assert_eq!(delay.tick(6.0), 4.75);
+    }
+
+    #[test]
+    fn test_fractional_delay_cubic() {
+        let mut delay = FractionalDelay::new(10, 2.5);
+        delay.set_interpolation(Interpolation::Cubic);
+
+        // Lagrange interpolation of four points is exact for a cubic
+        let cubic = |x: f32| 0.01 * x * x * x - 0.2 * x * x + x;
+
+        for i in 0..8 {
+            delay.tick(cubic(i as f32));
+        }
+
+        assert!((delay.tick(cubic(8.0)) - cubic(5.5)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_fractional_delay_thiran() {
+        let mut delay = FractionalDelay::new(100, 4.3);
+        delay.set_interpolation(Interpolation::Thiran);
+
+        // An allpass passes DC with unity gain
+        let response: f32 = (0..100)
+            .map(|i| delay.tick(if i == 0 { 1.0 } else { 0.0 }))
+            .sum();
+
+        assert!((response - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_fractional_delay_clamps() {
+        let mut delay = FractionalDelay::new(10, 20.0);
+        assert_eq!(delay.heads[delay.current].delay, 10.0);
+
+        delay.set_delay(0.0);
+        assert_eq!(delay.heads[delay.current].delay, 1.0);
+    }
+
+    #[test]
+    fn test_fractional_delay_crossfade() {
+        let mut delay = FractionalDelay::new(10, 2.0);
+        delay.set_interpolation(Interpolation::Linear);
+        delay.set_crossfade(4);
+
+        for i in 0..10 {
+            delay.tick(i as f32);
+        }
+
+        delay.set_delay(4.0);
+
+        let output: Vec<f32> = (10..16).map(|i| delay.tick(i as f32)).collect();
+        assert_eq!(output, [8.0, 8.5, 9.0, 9.5, 10.0, 11.0]);
+    }
+
+    #[test]
+    fn test_fractional_delay_reset_jumps() {
+        let mut delay = FractionalDelay::new(10, 2.0);
+        delay.set_crossfade(4);
+        delay.tick(1.0);
+        delay.reset();
+
+        // An empty line takes its next length straight away
+        delay.set_delay(4.0);
+        let output = [1.0, 0.0, 0.0, 0.0, 0.0].map(|x| delay.tick(x));
+        assert_eq!(output, [0.0, 0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_fractional_delay_crossfade_queues() {
+        let mut delay = FractionalDelay::new(10, 2.0);
+        delay.set_interpolation(Interpolation::Linear);
+        delay.set_crossfade(2);
+
+        for i in 0..10 {
+            delay.tick(i as f32);
+        }
+
+        delay.set_delay(4.0);
+        assert_eq!(delay.tick(10.0), 8.0);
+
+        // Arrives mid fade, so it is picked up once the fade to 4 is done. The ramp makes the
+        // length grow one sample per tick, which holds the output still
+        delay.set_delay(6.0);
+
+        let output: Vec<f32> = (11..15).map(|i| delay.tick(i as f32)).collect();
+        assert_eq!(output, [8.0, 8.0, 8.0, 8.0]);
+        assert_eq!(delay.heads[delay.current].delay, 6.0);
+        assert_eq!(delay.tick(15.0), 9.0);
+    }
+
+    #[test]
+    fn test_fractional_delay_modulation() {
+        let mut delay = FractionalDelay::new(10, 3.0);
+        delay.set_interpolation(Interpolation::Linear);
+
+        for i in 0..6 {
+            delay.tick(i as f32);
+        }
+
+        delay.set_modulation(1.5);
+        assert_eq!(delay.tick(6.0), 1.5);
+
+        delay.set_modulation(-1.0);
+        assert_eq!(delay.tick(7.0), 5.0);
+    }
+
+    #[test]
+    fn test_schroeder_allpass() {
+        let mut allpass = SchroederAllpass::new(10, 3.0, 0.5);
+
+        let response: Vec<f32> = (0..7)
+            .map(|i| allpass.tick(if i == 0 { 1.0 } else { 0.0 }))
+            .collect();
+
+        assert_eq!(response, [-0.5, 0.0, 0.0, 0.75, 0.0, 0.0, 0.375]);
+    }
+
+    #[test]
+    fn test_allpass_cascade_keeps_energy() {
+        let mut cascade = AllpassCascade::new([37.0, 53.0, 113.0, 89.0], 0.7, 113);
+
+        let response: Vec<f32> = (0..20000)
+            .map(|i| cascade.tick(if i == 0 { 1.0 } else { 0.0 }))
+            .collect();
+
+        let energy: f32 = response.iter().map(|x| x * x).sum();
+        assert!((energy - 1.0).abs() < 1e-4);
+
+        // The impulse has been smeared over many samples
+        let echoes = response.iter().filter(|x| x.abs() > 1e-3).count();
+        assert!(echoes > 50);
+    }
+
+    #[test]
+    fn test_room_reflections() {
+        let room = Room {
+            dimensions: [10.0, 8.0, 3.0],
+            source: [2.0, 3.0, 1.5],
+            listener: [6.0, 3.0, 1.5],
+            absorption: 0.36,
+        };
+
+        let taps = room.reflections(room.listener);
+
+        // The floor reflection travels 5 m against the direct 4 m and bounces once
+        let floor = Tap {
+            delay: 1.0 / SPEED_OF_SOUND,
+            gain: 0.8 * 4.0 / 5.0,
+        };
+        assert!(taps
+            .iter()
+            .any(|tap| (tap.delay - floor.delay).abs() < 1e-6
+                && (tap.gain - floor.gain).abs() < 1e-6));
+
+        // Loudest first, all after the direct sound and quieter than it
+        for pair in taps.windows(2) {
+            assert!(pair[0].gain >= pair[1].gain);
+        }
+        for tap in taps {
+            assert!(tap.delay > 0.0 && tap.delay <= MAX_EARLY_DELAY);
+            assert!(tap.gain > 0.0 && tap.gain < 1.0);
+        }
+    }
+
+    #[test]
+    fn test_room_absorption() {
+        let mut room = Room::default();
+        let reflective = room.reflections(room.listener);
+
+        room.absorption = 0.9;
+        let absorbent = room.reflections(room.listener);
+
+        assert!(absorbent[0].gain < reflective[0].gain);
+    }
+
+    #[test]
+    fn test_early_reflections_impulse() {
+        let mut early = EarlyReflections::new(Room::default(), 1000.0);
+
+        // Move the taps onto whole samples so the impulse lands on one sample each
+        early.taps[early.current] = [
+            [Tap::default(); EARLY_TAPS].map(|_| Tap {
+                delay: 3.0,
+                gain: 0.5,
+            }),
+            [Tap::default(); EARLY_TAPS],
+        ];
+
+        let response: Vec<[f32; 2]> = (0..5)
+            .map(|i| early.tick(if i == 0 { [1.0, 1.0] } else { [0.0, 0.0] }))
+            .collect();
+
+        assert_eq!(response[3], [0.5 * EARLY_TAPS as f32, 0.0]);
+        assert_eq!(response[2], [0.0, 0.0]);
+        assert_eq!(response[4], [0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_early_reflections_room_crossfade() {
+        let sample_rate = 1000.0;
+        let fill = (MAX_EARLY_DELAY * sample_rate) as usize + 2;
+
+        // Under a constant input every tap reads the same, so the output is the sum of the gains
+        let steady = |room: Room| {
+            let mut early = EarlyReflections::new(room, sample_rate);
+            (0..fill).for_each(|_| {
+                early.tick([1.0; 2]);
+            });
+            early.tick([1.0; 2])
+        };
+        let rooms = [
+            Room::default(),
+            Room {
+                absorption: 0.8,
+                ..Room::default()
+            },
+            Room {
+                source: [3.0, 4.0, 1.5],
+                ..Room::default()
+            },
+        ];
+        let levels = rooms.map(steady);
+
+        let mut early = EarlyReflections::new(rooms[0], sample_rate);
+        early.set_crossfade(100);
+        (0..fill).for_each(|_| {
+            early.tick([1.0; 2]);
+        });
+
+        // Glides to the new room instead of jumping, a room set mid fade waits its turn
+        early.set_room(rooms[1]);
+        let mut previous = levels[0];
+        for ii in 0..200 {
+            if ii == 50 {
+                early.set_room(rooms[2]);
+            }
+            let output = early.tick([1.0; 2]);
+            for ear in 0..2 {
+                let step = (levels[1][ear] - levels[0][ear])
+                    .abs()
+                    .max((levels[2][ear] - levels[1][ear]).abs())
+                    / 100.0;
+                assert!((output[ear] - previous[ear]).abs() <= step + 1e-5);
+            }
+            previous = output;
+
+            if ii == 100 {
+                assert!((0..2).all(|ear| (output[ear] - levels[1][ear]).abs() < 1e-4));
+            }
+        }
+        // Give the summed fade steps a little slack
+        early.tick([1.0; 2]);
+        let output = early.tick([1.0; 2]);
+        assert!((0..2).all(|ear| (output[ear] - levels[2][ear]).abs() < 1e-4));
+    }
+
+    #[test]
+    fn test_lfo_sine() {
+        let mut lfo = Lfo::new(0.0, 1);
+        lfo.set_rate(0.25);
+
+        let output: Vec<f32> = (0..5).map(|_| lfo.tick()).collect();
+
+        assert!((output[0] - 0.0).abs() < 1e-6);
+        assert!((output[1] - 1.0).abs() < 1e-6);
+        assert!((output[3] + 1.0).abs() < 1e-6);
+        assert!((output[4] - 0.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_lfo_smooth_random() {
+        let mut lfo = Lfo::new(0.0, 1);
+        lfo.set_shape(LfoShape::SmoothRandom);
+        lfo.set_rate(0.01);
+
+        let mut previous = lfo.tick();
+        for _i in 0..1000 {
+            let output = lfo.tick();
+
+            // Stays in range and glides instead of stepping
+            assert!((-1.0..=1.0).contains(&output));
+            assert!((output - previous).abs() < 0.05);
+            previous = output;
+        }
+    }
+
     #[test]
     fn test_one_pole_lowpass() {
-        let mut lowpass = OnePole::new(0.09);
+        let mut lowpass = OnePole::new(3969.0, 44100.0);
 
         assert_eq!(lowpass.tick(1.0), 0.43191642);
         assert_eq!(lowpass.tick(1.0), 0.677281);
 
-        lowpass.set_cutoff(1.0);
+        lowpass.set_cutoff(44100.0);
         assert_eq!(lowpass.tick(1.0), 0.9993974);
     }
 
+    #[test]
+    fn test_one_pole_sample_rate() {
+        let mut lowpass = OnePole::new(1000.0, 44100.0);
+        let reference = OnePole::new(1000.0, 96000.0);
+
+        // Keeps its cutoff in Hz when the sample rate changes
+        lowpass.set_sample_rate(96000.0);
+        assert_eq!(lowpass.a0, reference.a0);
+        assert_eq!(lowpass.b1, reference.b1);
+
+        // Passes everything until it gets a cutoff
+        let mut passthrough = OnePole::default();
+        passthrough.set_sample_rate(48000.0);
+        assert_eq!(passthrough.tick(0.3), 0.3);
+    }
+
     #[test]
     fn test_feedback() {
-        let delay = IntegerDelay::new(10, 1);
+        let delay = FractionalDelay::new(10, 1.0);
 
-        let mut feedback = Feedback::<IntegerDelay>::new(delay, 0.5);
+        let mut feedback = Feedback::<FractionalDelay>::new(delay, 0.5);
 
         assert_eq!(feedback.tick(1.0), 0.0);
         assert_eq!(feedback.tick(1.0), 0.5);
@@ -582,9 +3423,9 @@ mod tests {
 
     #[test]
     fn test_feedback_change_gain() {
-        let delay = IntegerDelay::new(10, 1);
+        let delay = FractionalDelay::new(10, 1.0);
 
-        let mut feedback = Feedback::<IntegerDelay>::new(delay, 0.5);
+        let mut feedback = Feedback::<FractionalDelay>::new(delay, 0.5);
 
         assert_eq!(feedback.tick(1.0), 0.0);
         assert_eq!(feedback.tick(1.0), 0.5);
@@ -619,9 +3460,334 @@ mod tests {
         assert_eq!(junction.join(output), [1.0, 0.25]);
     }
 
+    #[test]
+    fn test_junction_routing() {
+        let mut junction = ChannelJunction::<2, 16>::default();
+        junction.set_lines(8);
+        let energy = |lines: [f32; 16]| lines.iter().map(|x| x * x).sum::<f32>();
+        let block = energy(junction.split([1.0, -0.5]));
+
+        junction.set_routing(Routing::Orthogonal);
+        let split = junction.split([1.0, -0.5]);
+        assert_eq!(split[8..], [0.0; 8]);
+
+        // Both channels feed every line with the same energy as the blocks, and don't overlap
+        let [left, right] = junction.inputs;
+        let dot = |a: &[f32], b: &[f32]| a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+        assert!(left[..8].iter().chain(right[..8].iter()).all(|x| *x != 0.0));
+        assert!((energy(split) - block).abs() < 1e-5);
+        assert!(dot(&left, &right).abs() < 1e-6);
+
+        // Each channel comes back out on its own
+        let joined = junction.join(split);
+        assert!((joined[0] - 1.0).abs() < 1e-6);
+        assert!((joined[1] + 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_first_order_lowpass() {
+        let mut lowpass = FirstOrderLowpass::default();
+        lowpass.set_cutoff(4410.0);
+
+        let dc = (0..1000).map(|_| lowpass.tick(1.0)).last().unwrap();
+        assert!((dc - 1.0).abs() < 1e-5);
+
+        lowpass.reset();
+        let nyquist = (0..1000)
+            .map(|i| lowpass.tick(if i % 2 == 0 { 1.0 } else { -1.0 }))
+            .last()
+            .unwrap();
+        assert!(nyquist.abs() < 1e-5);
+    }
+
+    // Steady state gain at DC and at Nyquist
+    fn dc_and_nyquist(filter: &mut impl Signal) -> (f32, f32) {
+        filter.reset();
+        let dc = (0..2000).map(|_| filter.tick(1.0)).last().unwrap();
+        filter.reset();
+        let nyquist = (0..2000)
+            .map(|i| filter.tick(if i % 2 == 0 { 1.0 } else { -1.0 }))
+            .last()
+            .unwrap();
+        (dc, nyquist.abs())
+    }
+
+    #[test]
+    fn test_biquad_cuts() {
+        let sample_rate = DEFAULT_SAMPLE_RATE as f32;
+
+        let (dc, nyquist) =
+            dc_and_nyquist(&mut Biquad::new(BiquadType::Lowpass, 1000.0, sample_rate));
+        assert!((dc - 1.0).abs() < 1e-4);
+        assert!(nyquist < 1e-4);
+
+        let (dc, nyquist) =
+            dc_and_nyquist(&mut Biquad::new(BiquadType::Highpass, 1000.0, sample_rate));
+        assert!(dc.abs() < 1e-4);
+        assert!((nyquist - 1.0).abs() < 1e-4);
+
+        // Butterworth, 3 dB down at the cutoff
+        let mut lowpass = Biquad::new(BiquadType::Lowpass, sample_rate / 8.0, sample_rate);
+        let signal = |i: usize| (TAU * i as f32 / 8.0).sin();
+        let power: f32 = (0..4000)
+            .map(|i| lowpass.tick(signal(i)))
+            .skip(2000)
+            .map(|x| x * x)
+            .sum();
+        assert!((power / 1000.0 - 0.5).abs() < 1e-3);
+
+        // Out of range passes everything
+        let mut lowpass = Biquad::new(BiquadType::Lowpass, f32::INFINITY, sample_rate);
+        assert_eq!(
+            [1.0, -0.5, 0.25].map(|x| lowpass.tick(x)),
+            [1.0, -0.5, 0.25]
+        );
+    }
+
+    #[test]
+    fn test_biquad_shelves() {
+        let sample_rate = DEFAULT_SAMPLE_RATE as f32;
+
+        let mut low = Biquad::new(BiquadType::LowShelf, 1000.0, sample_rate);
+        low.set_gain(6.0);
+        let (dc, nyquist) = dc_and_nyquist(&mut low);
+        assert!((dc - 10f32.powf(6.0 / 20.0)).abs() < 1e-3);
+        assert!((nyquist - 1.0).abs() < 1e-3);
+
+        let mut high = Biquad::new(BiquadType::HighShelf, 1000.0, sample_rate);
+        high.set_gain(-12.0);
+        let (dc, nyquist) = dc_and_nyquist(&mut high);
+        assert!((dc - 1.0).abs() < 1e-3);
+        assert!((nyquist - 10f32.powf(-12.0 / 20.0)).abs() < 1e-3);
+
+        // Flat at 0 dB
+        high.set_gain(0.0);
+        let (dc, nyquist) = dc_and_nyquist(&mut high);
+        assert!((dc - 1.0).abs() < 1e-4);
+        assert!((nyquist - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_output_eq() {
+        let mut eq = OutputEq::new(DEFAULT_SAMPLE_RATE as f32);
+
+        // Flat until set
+        let input = [[1.0, -1.0], [0.5, 0.25], [-0.75, 0.0]];
+        assert_eq!(input.map(|x| eq.tick(x)), input);
+
+        // The tilt pivots around TILT_FREQUENCY, at the extremes it's half the tilt each way
+        eq.set_tilt(6.0);
+        eq.reset();
+        let dc = (0..2000).map(|_| eq.tick([1.0, 1.0])).last().unwrap();
+        assert!((dc[0] - 10f32.powf(-3.0 / 20.0)).abs() < 1e-3);
+        assert_eq!(dc[0], dc[1]);
+
+        // Both cuts together
+        eq.set_tilt(0.0);
+        eq.set_cuts(100.0, 10000.0);
+        eq.reset();
+        let dc = (0..20000).map(|_| eq.tick([1.0, 1.0])).last().unwrap();
+        assert!(dc[0].abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_envelope_follower() {
+        let mut follower = EnvelopeFollower::default();
+        follower.set_times(0.01, 0.1);
+
+        // Within 1/e of the new level after the attack or release time
+        let attack = (0..441).map(|_| follower.tick(-1.0)).last().unwrap();
+        assert!((attack - (1.0 - (-1.0f32).exp())).abs() < 1e-3);
+
+        for _ in 0..44100 {
+            follower.tick(1.0);
+        }
+        let release = (0..4410).map(|_| follower.tick(0.0)).last().unwrap();
+        assert!((release - (-1.0f32).exp()).abs() < 1e-3);
+
+        // Times stay the same at other sample rates
+        follower.set_sample_rate(96000.0);
+        follower.reset();
+        let attack = (0..960).map(|_| follower.tick(1.0)).last().unwrap();
+        assert!((attack - (1.0 - (-1.0f32).exp())).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_gate_hold() {
+        for sample_rate in [44100.0, 48000.0, 96000.0, 192000.0] {
+            let mut gate = Gate::default();
+            gate.set_sample_rate(sample_rate);
+            gate.set(0.5, 0.1, 0.01);
+
+            // Closed until the key gets to the threshold
+            assert_eq!(gate.tick(0.25), 0.0);
+
+            // One sample over, then exactly the hold time open
+            let hold = (0.1 * sample_rate) as usize;
+            let gains: Vec<f32> = [1.0]
+                .into_iter()
+                .chain(std::iter::repeat_n(
+                    0.0,
+                    hold + 2 * sample_rate as usize / 100,
+                ))
+                .map(|key| gate.tick(key))
+                .collect();
+            assert_eq!(gains.iter().position(|gain| *gain < 1.0), Some(hold + 1));
+
+            // And shut once the release is over, falling all the way
+            let release = (0.01 * sample_rate) as usize;
+            assert!(gains[hold + 1..=hold + release]
+                .windows(2)
+                .all(|pair| pair[1] < pair[0]));
+            assert_eq!(gains[hold + release + 1], 0.0);
+
+            // The key coming back restarts the hold
+            assert_eq!(gate.tick(-0.75), 1.0);
+        }
+    }
+
+    #[test]
+    fn test_pitch_shifter() {
+        let sample_rate = DEFAULT_SAMPLE_RATE as f32;
+
+        // Power of the output at a frequency in Hz, for a 441 Hz sine in
+        let power = |shifter: &mut PitchShifter<1>, frequency: f32| {
+            shifter.reset();
+            let outputs: Vec<f32> = (0..DEFAULT_SAMPLE_RATE)
+                .map(|i| {
+                    let mut value = [(TAU * 441.0 * i as f32 / sample_rate).sin()];
+                    shifter.process(&mut value);
+                    value[0]
+                })
+                .collect();
+            assert!(outputs.iter().all(|x| x.abs() <= 1.0));
+
+            let (mut re, mut im) = (0.0, 0.0);
+            for (i, output) in outputs.iter().enumerate().skip(DEFAULT_SAMPLE_RATE / 2) {
+                let phase = TAU * frequency * i as f32 / sample_rate;
+                re += output * phase.cos();
+                im += output * phase.sin();
+            }
+            re * re + im * im
+        };
+
+        for interval in [
+            ShimmerInterval::OctaveUp,
+            ShimmerInterval::Fifth,
+            ShimmerInterval::OctaveDown,
+        ] {
+            let mut shifter = PitchShifter::new(interval.ratio(), sample_rate);
+            let shifted = power(&mut shifter, 441.0 * interval.ratio());
+            let original = power(&mut shifter, 441.0);
+            assert!(shifted > original * 100.0);
+        }
+    }
+
+    #[test]
+    fn test_fdn_shimmer_is_stable() {
+        const DELAYS: [f32; 8] = [
+            1499.0, 1777.0, 2111.0, 2371.0, 2633.0, 2819.0, 2939.0, 3163.0,
+        ];
+
+        // Without any loss the shimmer is all that changes the energy in the loop
+        let mut fdn = HouseholderFDN::<8>::new(DELAYS, 1.0, 3200);
+        fdn.set_shimmer(1.0, ShimmerInterval::OctaveUp);
+
+        let mut random = Random::new(19);
+        let energies: Vec<f32> = (0..200)
+            .map(|block| {
+                (0..2205)
+                    .map(|_| {
+                        let input = if block < 2 {
+                            random.next_bipolar()
+                        } else {
+                            0.0
+                        };
+                        fdn.tick([input; 8]).iter().map(|x| x * x).sum::<f32>()
+                    })
+                    .sum()
+            })
+            .collect();
+
+        let peak = get_max_float(&energies[..20]);
+        assert!(energies[20..].iter().all(|energy| *energy <= peak * 1.1));
+    }
+
+    #[test]
+    fn test_fdn_shimmer_octave() {
+        let sample_rate = DEFAULT_SAMPLE_RATE as f32;
+        let delays: [f32; 32] = std::array::from_fn(|ii| 1499.0 + 53.0 * ii as f32);
+
+        // Power of the tail at a frequency in Hz, after a burst of a 441 Hz sine
+        let power = |amount: f32, frequency: f32| {
+            let mut fdn = HouseholderFDN::<32>::new(delays, 0.9, 3200);
+            fdn.set_shimmer(amount, ShimmerInterval::OctaveUp);
+
+            let (mut re, mut im) = (0.0, 0.0);
+            for i in 0..DEFAULT_SAMPLE_RATE / 4 {
+                let input = if i < DEFAULT_SAMPLE_RATE / 20 {
+                    (TAU * 441.0 * i as f32 / sample_rate).sin()
+                } else {
+                    0.0
+                };
+                let output: f32 = fdn.tick([input; 32]).iter().sum();
+
+                // Skip the burst and the first trip around the loop
+                if i >= DEFAULT_SAMPLE_RATE / 10 {
+                    let phase = TAU * frequency * i as f32 / sample_rate;
+                    re += output * phase.cos();
+                    im += output * phase.sin();
+                }
+            }
+            re * re + im * im
+        };
+
+        // A good part of the tail moves up an octave
+        assert!(power(0.5, 882.0) > power(0.5, 441.0) * 0.25);
+        assert!(power(0.5, 882.0) > power(0.0, 882.0) * 100.0);
+    }
+
+    #[test]
+    fn test_absorption_bands() {
+        let mut absorption = Absorption::default();
+        absorption.set_crossovers(441.0, 4410.0);
+        absorption.set_gains(0.9, 0.5, 0.2);
+
+        let steady = |absorption: &mut Absorption, period: usize| {
+            absorption.reset();
+            let signal = |i: usize| (TAU * i as f32 / period as f32).cos();
+            let (mut correlation, mut power) = (0.0, 0.0);
+            for i in 0..4000 {
+                let output = absorption.tick(signal(i));
+                if i >= 2000 {
+                    correlation += output * signal(i);
+                    power += signal(i) * signal(i);
+                }
+            }
+            correlation / power
+        };
+
+        // Bass at DC, mids between the crossovers and treble at Nyquist
+        assert!((steady(&mut absorption, 100000) - 0.9).abs() < 0.01);
+        assert!((steady(&mut absorption, 32) - 0.5).abs() < 0.05);
+        assert!((steady(&mut absorption, 2) - 0.2).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_fdn_decay_multipliers() {
+        let mut fdn = HouseholderFDN::<1>::new([100.0], 1.0, 300);
+        fdn.set_decay(1000.0);
+        fdn.set_decay_multipliers(2.0, 0.5);
+
+        let filter = fdn.filters[0];
+        assert_eq!(filter.gain, 0.001f32.powf(0.1));
+        assert_eq!(filter.gain * filter.low_ratio, 0.001f32.powf(0.05));
+        assert_eq!(filter.gain * filter.high_ratio, 0.001f32.powf(0.2));
+    }
+
     #[test]
     fn test_householder_fdn() {
-        const DELAYS: [usize; 4] = [2, 3, 5, 7];
+        const DELAYS: [f32; 4] = [2.0, 3.0, 5.0, 7.0];
         const DELAYS_LEN: usize = DELAYS.len();
 
         let mut fdn = HouseholderFDN::<{ DELAYS_LEN }>::new(DELAYS, 0.5, 10);
@@ -652,12 +3818,12 @@ mod tests {
 
     #[test]
     fn test_householder_fdn_lowpass() {
-        const DELAYS: [usize; 4] = [2, 3, 5, 7];
+        const DELAYS: [f32; 4] = [2.0, 3.0, 5.0, 7.0];
         const DELAYS_LEN: usize = DELAYS.len();
 
         let mut fdn = HouseholderFDN::<{ DELAYS_LEN }>::new(DELAYS, 1.0, 10);
 
-        fdn.set_cutoff(0.09);
+        fdn.set_cutoff(3969.0);
 
         let junction = ChannelJunction::<2, { DELAYS_LEN }>::default();
 
@@ -679,6 +3845,153 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_householder_fdn_blocks_dc() {
+        const DELAYS: [f32; 4] = [149.0, 211.0, 263.0, 293.0];
+
+        // Average output over the second half of a second of constant input
+        let offset = |fdn: &mut HouseholderFDN<4>, input: f32| {
+            let outputs: Vec<f32> = (0..DEFAULT_SAMPLE_RATE)
+                .map(|_| fdn.tick([input, 0.0, 0.0, 0.0])[0])
+                .collect();
+            let half = &outputs[DEFAULT_SAMPLE_RATE / 2..];
+            half.iter().sum::<f32>() / half.len() as f32
+        };
+
+        // Without any loss DC piles up in the loop
+        let mut fdn = HouseholderFDN::<4>::new(DELAYS, 1.0, 300);
+        assert!(offset(&mut fdn, 1.0).abs() > 100.0);
+
+        // The highpass drains it while it keeps coming in, and after it stops
+        let mut fdn = HouseholderFDN::<4>::new(DELAYS, 1.0, 300);
+        fdn.set_low_cut(0.0);
+        assert!(offset(&mut fdn, 1.0).abs() < 0.01);
+        assert!(offset(&mut fdn, 0.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_fdn_low_cut() {
+        const DELAYS: [f32; 4] = [1499.0, 2111.0, 2633.0, 2939.0];
+        let sample_rate = DEFAULT_SAMPLE_RATE as f32;
+
+        // Energy left a second after a 40 Hz burst
+        let tail = |low_cut: f32| {
+            let mut fdn = HouseholderFDN::<4>::new(DELAYS, 1.0, 3000);
+            fdn.set_decay(4.0 * sample_rate);
+            fdn.set_low_cut(low_cut);
+
+            let burst = |i: usize| (TAU * 40.0 * i as f32 / sample_rate).sin();
+            (0..2 * DEFAULT_SAMPLE_RATE)
+                .map(|i| {
+                    let input = if i < DEFAULT_SAMPLE_RATE / 10 {
+                        burst(i)
+                    } else {
+                        0.0
+                    };
+                    fdn.tick([input; 4])
+                })
+                .skip(DEFAULT_SAMPLE_RATE)
+                .map(|output| output.iter().map(|x| x * x).sum::<f32>())
+                .sum::<f32>()
+        };
+
+        let open = tail(0.0);
+        let cut = tail(200.0);
+        assert!(cut < open * 0.001);
+    }
+
+    #[test]
+    fn test_absorption_freeze() {
+        let mut absorption = Absorption::default();
+        absorption.set_gains(0.5, 0.8, 0.2);
+        absorption.set_crossovers(500.0, 4000.0);
+        absorption.damping.set_cutoff(1000.0);
+        absorption.freeze = 1.0;
+
+        // Passes the line through untouched
+        let input = [1.0, -0.5, 0.25, 0.0, 0.75];
+        assert_eq!(input.map(|x| absorption.tick(x)), input);
+    }
+
+    #[test]
+    fn test_fdn_freeze_stops_modulation() {
+        const DELAYS: [f32; 4] = [149.0, 211.0, 263.0, 293.0];
+
+        // Energy out of all the lines over a tenth of a second
+        let energy = |fdn: &mut HouseholderFDN<4>| {
+            (0..DEFAULT_SAMPLE_RATE / 10)
+                .map(|_| fdn.tick([0.0; 4]).iter().map(|x| x * x).sum::<f32>())
+                .sum::<f32>()
+        };
+
+        let mut fdn = HouseholderFDN::<4>::new(DELAYS, 0.5, 400);
+        fdn.set_modulation(5.0 / DEFAULT_SAMPLE_RATE as f32, 40.0);
+        let mut random = Random::new(5);
+        for _ in 0..300 {
+            fdn.tick(std::array::from_fn(|_| random.next_bipolar()));
+        }
+        fdn.set_freeze(1.0);
+
+        // Moving read heads would lose some of the tail on every trip
+        let start = energy(&mut fdn);
+        for _ in 0..10 {
+            energy(&mut fdn);
+        }
+        let end = energy(&mut fdn);
+        assert!((end - start).abs() < start * 0.01);
+    }
+
+    #[test]
+    fn test_householder_fdn_decay() {
+        const DELAYS: [f32; 4] = [149.0, 211.0, 263.0, 293.0];
+        const DELAYS_LEN: usize = DELAYS.len();
+        const DECAY: f32 = 4410.0;
+
+        let junction = ChannelJunction::<2, { DELAYS_LEN }>::default();
+
+        // Energy of the impulse response in a window starting at `start`
+        let energy = |fdn: &mut HouseholderFDN<DELAYS_LEN>, start: usize| {
+            fdn.reset();
+            let mut energy = 0.0;
+            for i in 0..start + 4000 {
+                let input = if i == 0 { 1.0 } else { 0.0 };
+                let output = junction.join(fdn.tick(junction.split([input, input])));
+                if i >= start {
+                    energy += output[0] * output[0] + output[1] * output[1];
+                }
+            }
+            energy
+        };
+
+        for size in [1.0, 0.5] {
+            let mut fdn = HouseholderFDN::<{ DELAYS_LEN }>::new(DELAYS.map(|d| d * size), 1.0, 300);
+            fdn.set_decay(DECAY);
+
+            // The tail falls 60 dB (a million in energy) every DECAY samples, whatever the size
+            let ratio = energy(&mut fdn, 2000) / energy(&mut fdn, 2000 + DECAY as usize);
+            let db = 10.0 * ratio.log10();
+            assert!((db - 60.0).abs() < 1.0, "{db}");
+        }
+    }
+
+    #[test]
+    fn test_fdn_gain_per_line() {
+        let mut fdn = HouseholderFDN::<2>::new([100.0, 200.0], 1.0, 300);
+
+        let gains = |fdn: &HouseholderFDN<2>| fdn.filters.map(|filter| filter.gain);
+
+        fdn.set_decay(1000.0);
+        assert_eq!(gains(&fdn), [0.001f32.powf(0.1), 0.001f32.powf(0.2)]);
+
+        // Lines keep their decay when they change length
+        fdn.set_delays([50.0, 250.0]);
+        assert_eq!(gains(&fdn), [0.001f32.powf(0.05), 0.001f32.powf(0.25)]);
+
+        fdn.set_gain(0.5);
+        fdn.set_delays([100.0, 200.0]);
+        assert_eq!(gains(&fdn), [0.5, 0.5]);
+    }
+
     #[test]
     fn test_hadamard_algo() {
         let mut example_output = [1.0; 4];
@@ -718,7 +4031,7 @@ mod tests {
 
     #[test]
     fn test_hadamard_fdn() {
-        const DELAYS: [usize; 4] = [2, 3, 5, 7];
+        const DELAYS: [f32; 4] = [2.0, 3.0, 5.0, 7.0];
         const DELAYS_LEN: usize = DELAYS.len();
 
         let mut fdn = HadamardFDN::new(DELAYS, 0.5, 10);
@@ -743,27 +4056,963 @@ mod tests {
         );
     }
 
+    fn mixed<const N: usize>(matrix: &mut dyn FeedbackMatrix, input: [f32; N]) -> [f32; N] {
+        let mut output = input;
+        matrix.mix(&mut output);
+        output
+    }
+
+    #[test]
+    fn test_feedback_matrices_are_lossless() {
+        let mut random = Random::new(7);
+        let input: [f32; 32] = std::array::from_fn(|_| random.next_bipolar());
+        let energy = |x: &[f32; 32]| x.iter().map(|x| x * x).sum::<f32>();
+
+        let outputs = [
+            mixed(&mut Householder::default(), input),
+            mixed(&mut Hadamard::default(), input),
+            mixed(&mut RandomOrthogonal::<32>::new(1), input),
+            mixed(&mut Identity, input),
+        ];
+        for output in outputs.iter() {
+            assert!((energy(output) - energy(&input)).abs() < 1e-4 * energy(&input));
+        }
+    }
+
+    #[test]
+    fn test_feedback_matrix_density() {
+        let mut random = Random::new(7);
+        let input: [f32; 16] = std::array::from_fn(|_| random.next_bipolar());
+        let energy = |x: &[f32; 16]| x.iter().map(|x| x * x).sum::<f32>();
+
+        let mut matrices: [Box<dyn FeedbackMatrix>; 3] = [
+            Box::new(Householder::default()),
+            Box::new(Hadamard::default()),
+            Box::new(RandomOrthogonal::<16>::new(1)),
+        ];
+        for (ii, matrix) in matrices.iter_mut().enumerate() {
+            let is_householder = ii == 0;
+            let dense = mixed(matrix.as_mut(), input);
+
+            // Lossless at every setting
+            for density in [0.0, 0.25, 0.5, 0.75, 0.93, 0.95, 0.99] {
+                matrix.set_density(density);
+                let output = mixed(matrix.as_mut(), input);
+                assert!((energy(&output) - energy(&input)).abs() < 1e-4 * energy(&input));
+            }
+
+            // Every line only feeds itself, the Householder reflection inverts the first one
+            matrix.set_density(0.0);
+            let mut combs = input;
+            if is_householder {
+                combs[0] = -combs[0];
+            }
+            assert_eq!(mixed(matrix.as_mut(), input), combs);
+
+            // No jump on the way to full density
+            matrix.set_density(0.9999);
+            let output = mixed(matrix.as_mut(), input);
+            for (x, y) in output.iter().zip(dense.iter()) {
+                assert!((x - y).abs() < 1e-3);
+            }
+
+            // Back to the full matrix
+            matrix.set_density(1.0);
+            assert_eq!(mixed(matrix.as_mut(), input), dense);
+        }
+    }
+
+    #[test]
+    fn test_random_orthogonal_matrix() {
+        let mut impulse = [0.0; 16];
+        impulse[0] = 1.0;
+
+        // Every line gets some of the impulse
+        let column = mixed(&mut RandomOrthogonal::<16>::new(1), impulse);
+        assert!(column.iter().all(|x| x.abs() > 1e-3));
+
+        // The seed picks the matrix
+        assert_eq!(column, mixed(&mut RandomOrthogonal::<16>::new(1), impulse));
+        assert_ne!(column, mixed(&mut RandomOrthogonal::<16>::new(2), impulse));
+
+        // The reverb's seed picks it too
+        let mut reverb = Reverb::new(1.0, 8000.0, 1.0);
+        reverb.set_lengths(2, DelayStrategy::default());
+        assert_eq!(
+            mixed(&mut reverb.fdn.matrix.random, impulse),
+            mixed(&mut RandomOrthogonal::<MAX_ORDER>::new(2), impulse)
+        );
+    }
+
+    #[test]
+    fn test_switchable_matrix_crossfade() {
+        let mut matrix = SwitchableMatrix::<4>::default();
+        matrix.set_crossfade(4);
+
+        let input = [1.0, 0.0, 0.0, 0.0];
+        assert_eq!(
+            mixed(&mut matrix, input),
+            mixed(&mut Householder::default(), input)
+        );
+
+        // Fades linearly from the Householder to the identity matrix
+        matrix.set_matrix(Matrix::Identity);
+        let outputs: Vec<f32> = (0..6).map(|_| mixed(&mut matrix, input)[1]).collect();
+        assert_eq!(outputs, [-0.5, -0.375, -0.25, -0.125, 0.0, 0.0]);
+
+        // A change during a fade waits for it to end
+        matrix.set_matrix(Matrix::Hadamard);
+        mixed(&mut matrix, input);
+        matrix.set_matrix(Matrix::Random);
+        for _ in 0..3 {
+            mixed(&mut matrix, input);
+        }
+        assert_eq!(
+            mixed(&mut matrix, input),
+            mixed(&mut Hadamard::default(), input)
+        );
+        assert_ne!(
+            mixed(&mut matrix, input),
+            mixed(&mut Hadamard::default(), input)
+        );
+    }
+
+    #[test]
+    fn test_fdn_order() {
+        let mut fdn = HadamardFDN::<8>::new([2.0, 3.0, 5.0, 7.0, 11.0, 13.0, 17.0, 19.0], 1.0, 20);
+        fdn.set_order(4);
+
+        let mut junction = ChannelJunction::<2, 8>::default();
+        junction.set_lines(4);
+        assert_eq!(
+            junction.split([1.0, -1.0]),
+            [1.0, 1.0, -1.0, -1.0, 0.0, 0.0, 0.0, 0.0]
+        );
+
+        // Runs like a network of only the first four lines
+        let mut small = HadamardFDN::<4>::new([2.0, 3.0, 5.0, 7.0], 1.0, 20);
+        let small_junction = ChannelJunction::<2, 4>::default();
+        for ii in 0..50 {
+            let input = if ii == 0 { [1.0, 0.5] } else { [0.0, 0.0] };
+            let output = fdn.tick(junction.split(input));
+            assert!(output[4..].iter().all(|x| *x == 0.0));
+            assert_eq!(
+                junction.join(output),
+                small_junction.join(small.tick(small_junction.split(input)))
+            );
+        }
+
+        // Lines that come back start out silent, even with something left in their buffers. The
+        // Hadamard network puts out the mixed lines, so this reads them straight from the delays
+        let mut fdn =
+            HouseholderFDN::<8>::new([2.0, 3.0, 5.0, 7.0, 11.0, 13.0, 17.0, 19.0], 1.0, 20);
+        junction.set_lines(8);
+        for _ in 0..30 {
+            fdn.tick(junction.split([1.0, 0.5]));
+        }
+        fdn.set_order(4);
+        fdn.set_order(8);
+
+        // Until the feedback gets around them, the shortest of them is 11 samples
+        for _ in 0..10 {
+            let output = fdn.tick([0.0; 8]);
+            assert!(output[4..].iter().all(|x| *x == 0.0));
+        }
+    }
+
+    #[test]
+    fn test_fdn_modulation_phases() {
+        let fdn = HouseholderFDN::<64>::new([100.0; 64], 1.0, 200);
+        let phases = fdn.modulators.each_ref().map(|lfo| lfo.phase);
+
+        // Whatever the order, the lines in use are evenly spread over the cycle
+        for order in [4, 8, 64] {
+            let mut used = phases[..order].to_vec();
+            used.sort_by(|a, b| a.total_cmp(b));
+            for (ii, phase) in used.iter().enumerate() {
+                assert_eq!(*phase, ii as f32 / order as f32);
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_delays() {
+        let sample_rate = DEFAULT_SAMPLE_RATE as f32;
+        let in_range = |delays: &[f32]| {
+            delays
+                .iter()
+                .all(|d| *d >= MIN_DELAY * sample_rate && *d <= MAX_DELAY * sample_rate + 100.0)
+        };
+
+        for strategy in [
+            DelayStrategy::MutuallyPrime,
+            DelayStrategy::Logarithmic,
+            DelayStrategy::Random,
+        ] {
+            let delays = generate_delays(DEFAULT_SEED, strategy, sample_rate);
+            assert!(in_range(&delays));
+
+            // The seed picks the set
+            assert_eq!(delays, generate_delays(DEFAULT_SEED, strategy, sample_rate));
+            assert_ne!(
+                delays,
+                generate_delays(DEFAULT_SEED + 1, strategy, sample_rate)
+            );
+        }
+
+        // No two lengths share a factor
+        let delays = generate_delays(DEFAULT_SEED, DelayStrategy::MutuallyPrime, sample_rate);
+        let gcd = |mut a: usize, mut b: usize| {
+            while b != 0 {
+                (a, b) = (b, a % b);
+            }
+            a
+        };
+        for (ii, a) in delays.iter().enumerate() {
+            assert_eq!(a.fract(), 0.0);
+            for b in delays[ii + 1..].iter() {
+                assert_eq!(gcd(*a as usize, *b as usize), 1);
+            }
+        }
+
+        // The first four lines still cover the range
+        let delays = generate_delays(DEFAULT_SEED, DelayStrategy::Logarithmic, sample_rate);
+        let mut first = [delays[0], delays[1], delays[2], delays[3]];
+        first.sort_by(|a, b| a.total_cmp(b));
+        assert!(first[0] < 0.35 * sample_rate);
+        assert!(first[3] > 0.6 * sample_rate);
+        for pair in first.windows(2) {
+            assert!(pair[1] / pair[0] > 1.2);
+        }
+
+        // Lengths follow the sample rate
+        let doubled = generate_delays(DEFAULT_SEED, DelayStrategy::Logarithmic, sample_rate * 2.0);
+        for (a, b) in delays.iter().zip(doubled.iter()) {
+            assert!((b / a - 2.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_reverb_size_keeps_lengths_prime() {
+        let mut reverb = Reverb::new(1.0, 8000.0, 1.0);
+
+        for size in [1.0, 0.7, 0.333, 0.1] {
+            // The tank takes the new lengths at the start of the next block
+            reverb.set_size(size);
+            reverb.process_buffer_slice(&mut [&mut [], &mut []]);
+            let mut delays = reverb.fdn.delays.each_ref().map(|delay| delay.delay());
+
+            // Distinct primes, so still coprime, just above the scaled lengths
+            for (delay, full) in delays.iter().zip(reverb.delays.iter()) {
+                assert_eq!(delay.fract(), 0.0);
+                assert!(is_prime(*delay as usize));
+                assert!(*delay >= (full * size).round() && *delay < full * size * 1.01);
+            }
+            delays.sort_by(|a, b| a.total_cmp(b));
+            assert!(delays.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+
+        // Other strategies scale as they are
+        reverb.set_lengths(DEFAULT_SEED, DelayStrategy::Logarithmic);
+        reverb.set_size(0.5);
+        reverb.process_buffer_slice(&mut [&mut [], &mut []]);
+        for (line, full) in reverb.fdn.delays.iter().zip(reverb.delays.iter()) {
+            assert_eq!(line.delay(), full * 0.5);
+        }
+    }
+
+    #[test]
+    fn test_reverb_tuning_once_per_block() {
+        let mut reverb = Reverb::new(1.0, 8000.0, 1.0);
+        reverb.set_size(0.5);
+        reverb.process_buffer_slice(&mut [&mut [], &mut []]);
+
+        // Setting what it already has is free
+        reverb.set_size(0.5);
+        reverb.set_lengths(DEFAULT_SEED, DelayStrategy::default());
+        reverb.set_decay(1.0);
+        reverb.set_decay_multipliers(1.0, 1.0);
+        assert!(!reverb.tuning_changed);
+
+        // Changes wait for the next block and are taken together
+        let gains = reverb.fdn.filters.map(|filter| filter.gain);
+        reverb.set_size(0.25);
+        reverb.set_decay(2.0);
+        reverb.set_decay_multipliers(1.5, 0.5);
+        assert_eq!(reverb.fdn.filters.map(|filter| filter.gain), gains);
+
+        reverb.process_buffer_slice(&mut [&mut [], &mut []]);
+        assert!(!reverb.tuning_changed);
+        let filter = reverb.fdn.filters[0];
+        let length = reverb.fdn.delays[0].delay();
+        let sample_rate = DEFAULT_SAMPLE_RATE as f32;
+        assert_eq!(filter.gain, 0.001f32.powf(length / (2.0 * sample_rate)));
+        assert_eq!(
+            filter.gain * filter.low_ratio,
+            0.001f32.powf(length / (2.0 * sample_rate * 1.5))
+        );
+    }
+
+    #[test]
+    fn test_reverb_size_sweep_is_smooth() {
+        let sample_rate = DEFAULT_SAMPLE_RATE as f32;
+        let mut reverb = Reverb::new(1.0, 0.5 * sample_rate, 4.0);
+
+        // Let the tank fill with a low sine, then sweep Size from 100% to 30% over a second
+        let mut n = 0;
+        let mut previous = [0.0; 2];
+        let mut max_curvature: f32 = 0.0;
+
+        for block in 0..(2 * DEFAULT_SAMPLE_RATE / 64) {
+            let time = (block * 64) as f32 / sample_rate;
+            let size = (1.0 - (time - 1.0) * 0.7).clamp(0.3, 1.0);
+            reverb.set_size(size);
+
+            let mut left = [0.0; 64];
+            for sample in left.iter_mut() {
+                *sample = 0.5 * (TAU * 110.0 * n as f32 / sample_rate).sin();
+                n += 1;
+            }
+            let mut right = left;
+
+            reverb.process_buffer_slice(&mut [&mut left, &mut right]);
+
+            // A jump in the tail shows up as a spike in the second difference, a steady tail
+            // stays around 0.0004 here and jumping straight to the new lengths gives over 0.2
+            for sample in left {
+                if time >= 1.0 {
+                    max_curvature =
+                        max_curvature.max((sample - 2.0 * previous[1] + previous[0]).abs());
+                }
+                previous = [previous[1], sample];
+            }
+        }
+
+        assert!(max_curvature < 0.01, "{max_curvature}");
+    }
+
+    #[test]
+    fn test_reverb_crossfade() {
+        let mut reverb = Reverb::new(1.0, 8000.0, 1.0);
+        let steps = |reverb: &Reverb| {
+            (
+                reverb.fdn.delays[0].fade_step,
+                reverb.early.fade_step,
+                reverb.predelay[0].fade_step,
+            )
+        };
+        assert_eq!(steps(&reverb), (1.0 / 2205.0, 1.0 / 2205.0, 1.0 / 2205.0));
+
+        // Carries over to a new sample rate, and 0 jumps straight to the new lengths
+        reverb.set_crossfade(0.2);
+        reverb.prepare(48000.0, DEFAULT_MAX_BLOCK_SIZE);
+        assert_eq!(steps(&reverb), (1.0 / 9600.0, 1.0 / 9600.0, 1.0 / 9600.0));
+        reverb.set_crossfade(0.0);
+        assert_eq!(steps(&reverb), (1.0, 1.0, 1.0));
+    }
+
     #[test]
     fn test_sort() {
         assert_eq!(get_max_float(&[0.1, 0.2, 0.3]), 0.3);
     }
 
     #[test]
-    fn test_reverb_no_alloc() {
-        let mut reverb = Reverb::new(
-            0.5,
-            0.9,
-            0.9,
-            (DEFAULT_SAMPLE_RATE as f32 * get_max_float(&DELAYS)) as usize,
+    fn test_reverb_sample_rates() {
+        // Energy decay of the response to a short 1 kHz burst
+        let envelope = |sample_rate: f32| {
+            // Settings in seconds and Hz carry over to the new rate
+            let mut reverb = Reverb::new(1.0, 8000.0, 1.5);
+            reverb.set_levels(0.5, 1.0);
+            reverb.set_diffusion(0.75);
+            reverb.set_predelay(0.01);
+            reverb.prepare(sample_rate, DEFAULT_MAX_BLOCK_SIZE);
+
+            let length = sample_rate as usize;
+            let burst = (0.005 * sample_rate) as usize;
+            let mut left: Vec<f32> = (0..length)
+                .map(|n| {
+                    let t = n as f32 / sample_rate;
+                    let window = (PI * n as f32 / burst as f32).sin().powi(2);
+                    if n < burst {
+                        window * (TAU * 1000.0 * t).sin()
+                    } else {
+                        0.0
+                    }
+                })
+                .collect();
+            let mut right = left.clone();
+            reverb.process_buffer_slice(&mut [&mut left, &mut right]);
+
+            // Schroeder's backward integration, in dB at every tenth of a second
+            // https://ccrma.stanford.edu/~jos/pasp/Energy_Decay_Curve.html
+            let total: f32 = left.iter().map(|x| x * x).sum::<f32>() / sample_rate;
+            let curve: Vec<f32> = (0..10)
+                .map(|tenth| {
+                    let start = tenth * length / 10;
+                    let energy = left[start..].iter().map(|x| x * x).sum::<f32>() / sample_rate;
+                    10.0 * energy.log10()
+                })
+                .collect();
+
+            // First sound out, the burst behind the pre-delay
+            let onset = left.iter().position(|x| x.abs() > 1e-4).unwrap() as f32 / sample_rate;
+
+            (10.0 * total.log10(), curve, onset)
+        };
+
+        let (total, curve, onset) = envelope(DEFAULT_SAMPLE_RATE as f32);
+        for sample_rate in [48000.0, 96000.0, 192000.0] {
+            let (other_total, other_curve, other_onset) = envelope(sample_rate);
+            assert!((other_total - total).abs() < 0.25);
+            assert!((other_onset - onset).abs() < 1e-4);
+
+            // Individual echoes land a little differently, the decay follows the same curve
+            for (a, b) in curve.iter().zip(other_curve.iter()) {
+                assert!((a - b).abs() < 2.0);
+            }
+        }
+    }
+
+    // Seeded noise, `burst` samples of it and then silence up to `len`
+    fn noise_burst(seed: u32, len: usize, burst: usize) -> Vec<f32> {
+        let mut random = Random::new(seed);
+        (0..len)
+            .map(|ii| {
+                if ii < burst {
+                    random.next_bipolar()
+                } else {
+                    0.0
+                }
+            })
+            .collect()
+    }
+
+    // A reverb that only plays the wet signal, with a decay in seconds
+    fn wet_reverb(decay: f32) -> Reverb {
+        let mut reverb = Reverb::new(1.0, 8000.0, decay);
+        reverb.set_mix_mode(MixMode::Send);
+        reverb
+    }
+
+    // Both channels through the reverb in one block, keyed from `key` on both channels if there
+    // is one
+    fn render(
+        reverb: &mut Reverb,
+        left: &[f32],
+        right: &[f32],
+        key: Option<&[f32]>,
+    ) -> (Vec<f32>, Vec<f32>) {
+        let (mut left, mut right) = (left.to_vec(), right.to_vec());
+        match key {
+            Some(key) => reverb.process_buffer_slice_keyed(
+                &mut [&mut left, &mut right],
+                &[&mut key.to_vec(), &mut key.to_vec()],
+            ),
+            None => reverb.process_buffer_slice(&mut [&mut left, &mut right]),
+        }
+        (left, right)
+    }
+
+    #[test]
+    fn test_reverb_width() {
+        // Energy of the stereo output and of its mono sum for half a second of noise
+        let widened = |width: f32| {
+            let mut reverb = Reverb::new(1.0, 8000.0, 1.0);
+            reverb.set_width(width);
+
+            let len = DEFAULT_SAMPLE_RATE / 2;
+            let (left, right) = render(
+                &mut reverb,
+                &noise_burst(5, len, len),
+                &noise_burst(6, len, len),
+                None,
+            );
+
+            let stereo: f32 = left.iter().chain(right.iter()).map(|x| x * x).sum::<f32>() / 2.0;
+            let mono: f32 = left
+                .iter()
+                .zip(right.iter())
+                .map(|(l, r)| ((l + r) * 0.5).powi(2))
+                .sum();
+            (left, right, 10.0 * (mono / stereo).log10())
+        };
+
+        // Mono at 0
+        let (left, right, _) = widened(0.0);
+        assert_eq!(left, right);
+
+        // Folding the decorrelated tail down to mono loses some level, but not too much
+        let (_, _, normal) = widened(1.0);
+        assert!(normal < 0.0 && normal > -6.0);
+
+        // Wider is less mono compatible
+        let (_, _, wide) = widened(2.0);
+        assert!(wide < normal);
+    }
+
+    #[test]
+    fn test_reverb_routing_decorrelates() {
+        // Correlation between the channels of the tail, and the level of the right relative to
+        // the left in dB, for a burst of noise
+        let measure = |routing: Routing, right_gain: f32| {
+            let mut reverb = Reverb::new(1.0, 8000.0, 2.0);
+            reverb.set_routing(routing);
+
+            let left = noise_burst(7, DEFAULT_SAMPLE_RATE, DEFAULT_SAMPLE_RATE / 10);
+            let right: Vec<f32> = left.iter().map(|x| x * right_gain).collect();
+            let (left, right) = render(&mut reverb, &left, &right, None);
+
+            // From the shortest line on, the early reflections are off
+            let start = (MIN_DELAY * DEFAULT_SAMPLE_RATE as f32) as usize;
+            let (left, right) = (&left[start..], &right[start..]);
+            let dot = |a: &[f32], b: &[f32]| a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+            (
+                dot(left, right) / (dot(left, left) * dot(right, right)).sqrt(),
+                10.0 * (dot(right, right) / dot(left, left)).log10(),
+            )
+        };
+
+        // The blocks keep a hard left input on the left for a long time
+        let (_, balance) = measure(Routing::Block, 0.0);
+        assert!(balance < -10.0);
+
+        // Spread over every line it comes out of both sides, but not the same on each
+        let (correlation, balance) = measure(Routing::Orthogonal, 0.0);
+        assert!(correlation.abs() < 0.1);
+        assert!(balance.abs() < 6.0);
+
+        // A mono input still comes out decorrelated
+        let (correlation, _) = measure(Routing::Orthogonal, 1.0);
+        assert!(correlation.abs() < 0.1);
+    }
+
+    #[test]
+    fn test_reverb_freeze() {
+        let sample_rate = DEFAULT_SAMPLE_RATE as f32;
+        let block = 441;
+
+        // Noise for half a second, then freeze and keep playing into it once the ramp is done. The
+        // tank is modulated like the plugin's defaults
+        let block_energies = |later_input: f32| {
+            let mut reverb = Reverb::new(1.0, 8000.0, 0.5);
+            reverb.set_modulation(0.5, 0.001);
+            let noise = noise_burst(3, 6 * DEFAULT_SAMPLE_RATE, 6 * DEFAULT_SAMPLE_RATE);
+
+            noise
+                .chunks(block)
+                .enumerate()
+                .map(|(index, noise)| {
+                    let time = (index * block) as f32 / sample_rate;
+                    reverb.set_freeze((0.5..4.0).contains(&time));
+
+                    let gain = if time < 0.5 {
+                        1.0
+                    } else if time > 0.6 {
+                        later_input
+                    } else {
+                        0.0
+                    };
+                    let input: Vec<f32> = noise.iter().map(|x| x * gain).collect();
+                    let (left, _) = render(&mut reverb, &input, &input, None);
+                    left.iter().map(|x| x * x).sum::<f32>()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let quiet = block_energies(0.0);
+        let loud = block_energies(1.0);
+        let db = |energies: &[f32], second: f32| {
+            let start = (second * sample_rate) as usize / block;
+            let windows = &energies[start..start + 50];
+            10.0 * (windows.iter().sum::<f32>() / 50.0).log10()
+        };
+
+        // New input doesn't get in
+        assert_eq!(
+            quiet[..(3.9 * sample_rate) as usize / block],
+            loud[..(3.9 * sample_rate) as usize / block]
+        );
+
+        // The tail holds where it was without blowing up
+        assert!((db(&quiet, 1.0) - db(&quiet, 3.5)).abs() < 2.0);
+
+        // And decays again once released, 0.5 s decay takes it down 120 dB in a second
+        assert!(db(&quiet, 5.0) < db(&quiet, 3.5) - 60.0);
+    }
+
+    #[test]
+    fn test_reverb_mix_modes() {
+        let impulse_response = |mode: MixMode| {
+            let mut reverb = Reverb::new(0.25, 8000.0, 1.0);
+            reverb.set_mix_mode(mode);
+            reverb.set_dry_wet(0.5, 2.0);
+
+            let mut impulse = vec![0.0; DEFAULT_SAMPLE_RATE / 2];
+            impulse[0] = 1.0;
+            render(&mut reverb, &impulse, &impulse, None).0
+        };
+
+        // The tank is at least one sample behind, so the first sample is all dry
+        let (equal_power, linear, dry_wet, send) = (
+            impulse_response(MixMode::EqualPower),
+            impulse_response(MixMode::Linear),
+            impulse_response(MixMode::DryWet),
+            impulse_response(MixMode::Send),
         );
+        assert_eq!(equal_power[0], 0.75f32.sqrt());
+        assert_eq!(linear[0], 0.75);
+        assert_eq!(dry_wet[0], 0.5);
+        assert_eq!(send[0], 0.0);
+
+        // And the rest is all wet
+        let wet = send.iter().position(|x| x.abs() > 1e-6).unwrap();
+        assert_eq!(equal_power[wet], send[wet] * 0.5);
+        assert_eq!(linear[wet], send[wet] * 0.25);
+        assert_eq!(dry_wet[wet], send[wet] * 2.0);
+    }
+
+    #[test]
+    fn test_reverb_ducking() {
+        // Energy of the wet signal in 10 ms blocks, for half a second of noise and then silence
+        let wet_energies = |amount: f32| {
+            let mut reverb = wet_reverb(2.0);
+            reverb.set_ducking(amount, -30.0, 0.001, 0.05);
+
+            let input: Vec<f32> = noise_burst(11, DEFAULT_SAMPLE_RATE, DEFAULT_SAMPLE_RATE / 2)
+                .iter()
+                .map(|x| x * 0.5)
+                .collect();
+            input
+                .chunks(441)
+                .map(|input| {
+                    let (left, _) = render(&mut reverb, input, input, None);
+                    left.iter().map(|x| x * x).sum::<f32>()
+                })
+                .collect::<Vec<_>>()
+        };
+        let db = |energies: &[f32], from: f32, to: f32| {
+            let blocks = &energies[(from * 100.0) as usize..(to * 100.0) as usize];
+            10.0 * blocks.iter().sum::<f32>().log10()
+        };
+
+        let open = wet_energies(0.0);
+        let ducked = wet_energies(1.0);
+
+        // Held down while the input plays
+        assert!(db(&ducked, 0.35, 0.5) < db(&open, 0.35, 0.5) - 12.0);
+
+        // And back once it stops and the release has passed
+        assert!((db(&ducked, 0.8, 1.0) - db(&open, 0.8, 1.0)).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_reverb_sidechain() {
+        // Wet energy for a quiet input under the threshold, keyed from the input or a sidechain
+        let wet_energy = |key: Option<f32>| {
+            let mut reverb = wet_reverb(2.0);
+            reverb.set_ducking(1.0, -30.0, 0.001, 0.05);
+
+            let len = DEFAULT_SAMPLE_RATE;
+            let input: Vec<f32> = noise_burst(13, len, len).iter().map(|x| x * 0.01).collect();
+            let key = key.map(|gain| {
+                noise_burst(14, len, len)
+                    .iter()
+                    .map(|x| x * gain)
+                    .collect::<Vec<_>>()
+            });
+            let (left, _) = render(&mut reverb, &input, &input, key.as_deref());
+            left.iter().map(|x| x * x).sum::<f32>()
+        };
+
+        // A silent sidechain leaves it alone, like the quiet input does
+        assert_eq!(wet_energy(Some(0.0)), wet_energy(None));
+
+        // A loud one ducks it
+        assert!(wet_energy(Some(0.5)) < wet_energy(None) * 0.1);
+    }
+
+    #[test]
+    fn test_reverb_sidechain_unconnected() {
+        // Gated wet signal for a burst of noise, keyed from an all zero sidechain or the input
+        let gated = |keyed: bool| {
+            let mut reverb = wet_reverb(2.0);
+            reverb.set_gate(true, -12.0, 0.2, 0.05);
+
+            let len = DEFAULT_SAMPLE_RATE / 2;
+            let input = noise_burst(19, len, len);
+            let key = vec![0.0; len];
+            let (left, _) = render(&mut reverb, &input, &input, keyed.then_some(&key[..]));
+            left
+        };
+
+        // Nothing routed to the sidechain, so the input opens the gate
+        let keyed = gated(true);
+        assert_eq!(keyed, gated(false));
+        assert!(keyed.iter().any(|x| *x != 0.0));
+    }
+
+    #[test]
+    fn test_reverb_sidechain_goes_silent() {
+        // Wet energy in the last quarter second of four seconds of loud noise, keyed from a
+        // sidechain that stops after half a second, from the input or not ducked
+        let tail_energy = |key: Option<usize>, amount: f32| {
+            let mut reverb = wet_reverb(2.0);
+            reverb.set_ducking(amount, -30.0, 0.001, 0.05);
+
+            let len = DEFAULT_SAMPLE_RATE * 4;
+            let input = noise_burst(21, len, len);
+            let key = key.map(|burst| noise_burst(22, len, burst));
+
+            // In host sized blocks, so whole blocks of the sidechain are silent
+            let mut output = vec![];
+            for start in (0..len).step_by(DEFAULT_MAX_BLOCK_SIZE) {
+                let block = start..(start + DEFAULT_MAX_BLOCK_SIZE).min(len);
+                let key = key.as_ref().map(|key| &key[block.clone()]);
+                let input = &input[block];
+                output.extend(render(&mut reverb, input, input, key).0);
+            }
+            output[len - DEFAULT_SAMPLE_RATE / 4..]
+                .iter()
+                .map(|x| x * x)
+                .sum::<f32>()
+        };
+
+        // The ducking lets go once the sidechain stops and stays let go through a long pause, the
+        // loud input doesn't take over
+        let open = tail_energy(None, 0.0);
+        let keyed = tail_energy(Some(DEFAULT_SAMPLE_RATE / 2), 1.0);
+        assert!((keyed - open).abs() < open * 0.01);
+
+        // A sidechain that never had signal keys from the input
+        let ducked = tail_energy(None, 1.0);
+        assert!(ducked < open * 0.1);
+        assert!((tail_energy(Some(0), 1.0) - ducked).abs() < ducked * 0.01);
+    }
+
+    #[test]
+    fn test_reverser() {
+        let mut reverser = Reverser::new(0.1, 1000.0);
+        assert_eq!(reverser.latency(), 199);
+
+        let input: Vec<f32> = (1..=300).map(|ii| ii as f32).collect();
+        let output: Vec<[f32; 2]> = input.iter().map(|x| reverser.tick([*x, -x])).collect();
+
+        // Silent until the first window is full
+        assert!(output[..100].iter().all(|frame| *frame == [0.0; 2]));
+
+        // Then played back to front, faded in and out at the ends
+        for ii in 10..90 {
+            assert_eq!(output[100 + ii], [input[99 - ii], -input[99 - ii]]);
+            assert_eq!(output[200 + ii], [input[199 - ii], -input[199 - ii]]);
+        }
+        assert!(output[100][0] > 0.0 && output[100][0] < output[101][0]);
+        assert!(output[101][0] < input[98]);
+        assert!(output[199][0] > 0.0 && output[199][0] < output[198][0]);
+        assert!(output[198][0] < input[1]);
+
+        // The start of a window comes out exactly the latency late, and nowhere else
+        let mut reverser = Reverser::new(0.1, 1000.0);
+        let output: Vec<f32> = (0..400)
+            .map(|ii| reverser.tick([if ii == 0 { 1.0 } else { 0.0 }; 2])[0])
+            .collect();
+        assert_eq!(output.iter().position(|x| *x != 0.0), Some(reverser.latency()));
+        assert_eq!(output.iter().filter(|x| **x != 0.0).count(), 1);
+    }
+
+    #[test]
+    fn test_reverser_longer_window() {
+        let mut reverser = Reverser::new(0.05, 1000.0);
+        let mut output: Vec<f32> = (0..140).map(|_| reverser.tick([1.0; 2])[0]).collect();
+        reverser.set_time(0.1);
+        output.extend((0..260).map(|_| reverser.tick([1.0; 2])[0]));
+
+        // The first longer window starts at 150 with the window just written, and doesn't play
+        // back what came before that again
+        assert!(output[100..140].iter().all(|x| *x != 0.0));
+        assert!(output[150..200].iter().all(|x| *x != 0.0));
+        assert!(output[200..250].iter().all(|x| *x == 0.0));
+        assert!(output[250..350].iter().all(|x| *x != 0.0));
+    }
+
+    #[test]
+    fn test_reverb_reverse() {
+        // An impulse through the reverb reversed in windows of a quarter second
+        let reversed = |dry: f32, wet: f32| {
+            let mut reverb = Reverb::new(1.0, 8000.0, 1.0);
+            reverb.set_mix_mode(MixMode::DryWet);
+            reverb.set_dry_wet(dry, wet);
+            reverb.set_reverse(true, 0.25);
+
+            let mut impulse = vec![0.0; DEFAULT_SAMPLE_RATE];
+            impulse[0] = 1.0;
+            let (left, _) = render(&mut reverb, &impulse, &impulse, None);
+            (left, reverb.latency())
+        };
+
+        // The dry signal comes out exactly the reported latency late
+        let (dry, latency) = reversed(1.0, 0.0);
+        assert_eq!(latency, 2 * 11025 - 1);
+        assert_eq!(dry.iter().position(|x| *x != 0.0), Some(latency));
+        assert!((dry[latency] - 1.0).abs() < 1e-6);
+
+        // And the tail swells up to it, silent for the first window and loudest late in the next
+        let (wet, _) = reversed(0.0, 1.0);
+        let window = latency / 2;
+        assert!(wet[..window].iter().all(|x| *x == 0.0));
+        let peak = wet[..latency]
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+            .unwrap()
+            .0;
+        assert!(peak > window + window / 2);
+
+        let mut reverb = Reverb::new(1.0, 8000.0, 1.0);
+        reverb.set_reverse(false, 0.25);
+        assert_eq!(reverb.latency(), 0);
+    }
+
+    #[test]
+    fn test_reverb_reverse_window_change() {
+        let mut reverb = Reverb::new(1.0, 8000.0, 1.0);
+        reverb.set_mix_mode(MixMode::DryWet);
+        reverb.set_dry_wet(1.0, 0.0);
+        reverb.set_reverse(true, 0.25);
+
+        let process = |reverb: &mut Reverb, len: usize, impulse: Option<usize>| {
+            let mut input = vec![0.0; len];
+            if let Some(index) = impulse {
+                input[index] = 1.0;
+            }
+            render(reverb, &input, &input, None).0
+        };
+
+        // The old window plays out before the latency changes
+        process(&mut reverb, 1000, None);
+        reverb.set_reverse(true, 0.1);
+        assert_eq!(reverb.latency(), 2 * 11025 - 1);
+        process(&mut reverb, 11025 - 1000, None);
+        assert_eq!(reverb.latency(), 2 * 11025 - 1);
+        process(&mut reverb, 1, None);
+        let latency = 2 * 4410 - 1;
+        assert_eq!(reverb.latency(), latency);
+
+        // And the dry signal moves with it
+        let dry = process(&mut reverb, latency + 200, Some(100));
+        assert_eq!(dry.iter().position(|x| *x != 0.0), Some(100 + latency));
+        assert!((dry[100 + latency] - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_reverb_reverse_key() {
+        // Gated wet signal for a 50 ms burst of noise, reversed in windows of a quarter second
+        let gated = |sidechain: bool| {
+            let mut reverb = wet_reverb(2.0);
+            reverb.set_gate(true, -12.0, 0.05, 0.01);
+            reverb.set_reverse(true, 0.25);
+
+            let burst = noise_burst(23, DEFAULT_SAMPLE_RATE, DEFAULT_SAMPLE_RATE / 20);
+            let (left, _) = if sidechain {
+                // Too quiet to open the gate on its own
+                let input: Vec<f32> = burst.iter().map(|x| x * 0.01).collect();
+                render(&mut reverb, &input, &input, Some(&burst))
+            } else {
+                render(&mut reverb, &burst, &burst, None)
+            };
+            (left, reverb.latency())
+        };
+
+        // The gate opens with the delayed dry burst, not when it went in
+        for sidechain in [false, true] {
+            let (wet, latency) = gated(sidechain);
+            assert!(wet[..latency].iter().all(|x| *x == 0.0));
+            assert!(wet[latency..].iter().any(|x| *x != 0.0));
+        }
+    }
+
+    #[test]
+    fn test_reverb_gate() {
+        // The wet signal for a 50 ms burst of noise
+        let burst = noise_burst(17, DEFAULT_SAMPLE_RATE, DEFAULT_SAMPLE_RATE / 20);
+        let wet = |gate: bool| {
+            let mut reverb = wet_reverb(2.0);
+            reverb.set_levels(1.0, 1.0);
+            reverb.set_gate(gate, -12.0, 0.2, 0.05);
+            render(&mut reverb, &burst, &burst, None).0
+        };
+
+        let open = wet(false);
+        let gated = wet(true);
+
+        // Untouched through the hold, then cut off after the release
+        let threshold = 10f32.powf(-12.0 / 20.0);
+        let last_over = burst.iter().rposition(|x| x.abs() >= threshold).unwrap();
+        let hold = last_over + (0.2 * DEFAULT_SAMPLE_RATE as f32) as usize;
+        let release = (0.05 * DEFAULT_SAMPLE_RATE as f32) as usize;
+        assert_eq!(gated[..=hold], open[..=hold]);
+        assert!(gated[hold + release + 1..].iter().all(|x| *x == 0.0));
+        assert!(open[hold + release + 1..].iter().any(|x| x.abs() > 1e-3));
+    }
+
+    #[test]
+    fn test_reverb_predelay() {
+        let first_output = |predelay: f32| {
+            let mut reverb = Reverb::new(1.0, 8000.0, 1.0);
+            reverb.set_levels(1.0, 1.0);
+            reverb.set_predelay(predelay);
+
+            let mut left = [0.0; 8192];
+            let mut right = [0.0; 8192];
+            left[0] = 1.0;
+            right[0] = 1.0;
+            reverb.process_buffer_slice(&mut [&mut left, &mut right]);
+
+            left.iter().position(|sample| sample.abs() > 1e-6).unwrap()
+        };
+
+        let without = first_output(0.0);
+        let with = first_output(0.1);
+
+        // The early reflections arrive a few milliseconds in, pushed back by the pre-delay. Even
+        // no pre-delay takes a sample
+        assert!(without < 200);
+        assert_eq!(with, without + 4409);
+    }
+
+    #[test]
+    fn test_reverb_no_alloc() {
+        let mut reverb = Reverb::new(0.5, 8000.0, 1.0);
 
         assert_no_alloc(|| {
             reverb.set_mix(0.75);
-            reverb.set_gain(2.0);
-            reverb.set_delays(
-                DELAYS.map(|delay| (delay * 0.5 * DEFAULT_SAMPLE_RATE as f32) as usize),
-            );
-            reverb.set_cutoff(1.0);
+            reverb.set_mix_mode(MixMode::DryWet);
+            reverb.set_dry_wet(0.5, 1.5);
+            reverb.set_ducking(0.5, -24.0, 0.01, 0.2);
+            reverb.set_gate(true, -30.0, 0.25, 0.1);
+            reverb.set_shimmer(0.5, ShimmerInterval::Fifth);
+            reverb.set_decay(2.0);
+            reverb.set_lengths(2, DelayStrategy::Random);
+            reverb.set_size(0.5);
+            reverb.set_cutoff(12000.0);
+            reverb.set_modulation(4.41, 0.0005);
+            reverb.set_modulation_shape(LfoShape::SmoothRandom);
+            reverb.set_interpolation(Interpolation::Thiran);
+            reverb.set_diffusion(0.8);
+            reverb.set_levels(0.5, 1.0);
+            reverb.set_predelay(0.05);
+            reverb.set_matrix(Matrix::Random);
+            reverb.set_tank_diffusion(0.5);
+            reverb.set_order(64);
+            reverb.set_freeze(true);
+            reverb.set_eq(80.0, 12000.0, -3.0);
+            reverb.set_tank_low_cut(40.0);
+            reverb.set_width(1.5);
+            reverb.set_routing(Routing::Orthogonal);
+            reverb.set_reverse(true, 0.5);
+            reverb.set_room(Room {
+                absorption: 0.5,
+                ..Room::default()
+            });
 
             reverb.process_buffer_slice(&mut [&mut [0.5; 64], &mut [0.5; 64]]);
         });
@@ -4,42 +4,228 @@ use dsp::*;
 use nih_plug::prelude::*;
 use std::sync::Arc;
 
-const MAX_SIZE: f32 = 1.0;
+// Height of the source and listener in the early reflection room, in meters
+const EAR_HEIGHT: f32 = 1.5;
+// Tempo used for synced pre-delay when the host doesn't report one
+const FALLBACK_TEMPO: f64 = 120.0;
 
 struct Jverb {
     params: Arc<JverbParams>,
     audio: Reverb,
+    // Last latency reported to the host, in samples
+    latency: u32,
 }
 
 #[derive(Params)]
 struct JverbParams {
     #[id = "mix"]
     pub mix: FloatParam,
+    #[id = "mix_mode"]
+    pub mix_mode: EnumParam<MixMode>,
+    #[id = "dry"]
+    pub dry: FloatParam,
+    #[id = "wet"]
+    pub wet: FloatParam,
+    #[id = "predelay"]
+    pub predelay: FloatParam,
+    #[id = "predelay_sync"]
+    pub predelay_sync: BoolParam,
+    #[id = "predelay_note"]
+    pub predelay_note: EnumParam<NoteValue>,
     #[id = "size"]
     pub size: FloatParam,
-    #[id = "time"]
-    pub time: FloatParam,
-    #[id = "lowpass"]
-    pub lowpass: FloatParam,
+    #[id = "size_crossfade"]
+    pub size_crossfade: FloatParam,
+    #[id = "decay"]
+    pub decay: FloatParam,
+    #[id = "freeze"]
+    pub freeze: BoolParam,
+    #[id = "bass_mult"]
+    pub bass_multiplier: FloatParam,
+    #[id = "treble_mult"]
+    pub treble_multiplier: FloatParam,
+    #[id = "low_xover"]
+    pub low_crossover: FloatParam,
+    #[id = "high_xover"]
+    pub high_crossover: FloatParam,
+    #[id = "damping"]
+    pub damping: FloatParam,
+    #[id = "tank_low_cut"]
+    pub tank_low_cut: FloatParam,
+    #[id = "diffusion"]
+    pub diffusion: FloatParam,
+    #[id = "matrix"]
+    pub matrix: EnumParam<Matrix>,
+    #[id = "tank_diffusion"]
+    pub tank_diffusion: FloatParam,
+    #[id = "order"]
+    pub order: EnumParam<Order>,
+    #[id = "lengths"]
+    pub delay_strategy: EnumParam<DelayStrategy>,
+    #[id = "seed"]
+    pub seed: IntParam,
+    #[id = "routing"]
+    pub routing: EnumParam<Routing>,
+    #[id = "width"]
+    pub width: FloatParam,
+    #[id = "early_level"]
+    pub early_level: FloatParam,
+    #[id = "late_level"]
+    pub late_level: FloatParam,
+    #[id = "room_width"]
+    pub room_width: FloatParam,
+    #[id = "room_length"]
+    pub room_length: FloatParam,
+    #[id = "room_height"]
+    pub room_height: FloatParam,
+    #[id = "source_x"]
+    pub source_x: FloatParam,
+    #[id = "source_y"]
+    pub source_y: FloatParam,
+    #[id = "listener_x"]
+    pub listener_x: FloatParam,
+    #[id = "listener_y"]
+    pub listener_y: FloatParam,
+    #[id = "absorption"]
+    pub absorption: FloatParam,
+    #[id = "mod_rate"]
+    pub mod_rate: FloatParam,
+    #[id = "mod_depth"]
+    pub mod_depth: FloatParam,
+    #[id = "mod_shape"]
+    pub mod_shape: EnumParam<LfoShape>,
+    #[id = "interpolation"]
+    pub interpolation: EnumParam<Interpolation>,
+    #[id = "shimmer"]
+    pub shimmer: FloatParam,
+    #[id = "shimmer_pitch"]
+    pub shimmer_pitch: EnumParam<ShimmerInterval>,
+    #[id = "low_cut"]
+    pub low_cut: FloatParam,
+    #[id = "high_cut"]
+    pub high_cut: FloatParam,
+    #[id = "tilt"]
+    pub tilt: FloatParam,
+    #[id = "key"]
+    pub key: EnumParam<Key>,
+    #[id = "duck_amount"]
+    pub duck_amount: FloatParam,
+    #[id = "duck_threshold"]
+    pub duck_threshold: FloatParam,
+    #[id = "duck_attack"]
+    pub duck_attack: FloatParam,
+    #[id = "duck_release"]
+    pub duck_release: FloatParam,
+    #[id = "gate"]
+    pub gate: BoolParam,
+    #[id = "gate_threshold"]
+    pub gate_threshold: FloatParam,
+    #[id = "gate_hold"]
+    pub gate_hold: FloatParam,
+    #[id = "gate_release"]
+    pub gate_release: FloatParam,
+    #[id = "reverse"]
+    pub reverse: BoolParam,
+    #[id = "reverse_time"]
+    pub reverse_time: FloatParam,
+}
+
+// Note lengths the pre-delay can sync to
+#[derive(Enum, Debug, Clone, Copy, PartialEq)]
+enum NoteValue {
+    #[name = "1/64"]
+    SixtyFourth,
+    #[name = "1/32"]
+    ThirtySecond,
+    #[name = "1/16T"]
+    SixteenthTriplet,
+    #[name = "1/16"]
+    Sixteenth,
+    #[name = "1/16D"]
+    SixteenthDotted,
+    #[name = "1/8T"]
+    EighthTriplet,
+    #[name = "1/8"]
+    Eighth,
+    #[name = "1/8D"]
+    EighthDotted,
+    #[name = "1/4T"]
+    QuarterTriplet,
+    #[name = "1/4"]
+    Quarter,
+    #[name = "1/4D"]
+    QuarterDotted,
+    #[name = "1/2"]
+    Half,
+}
+
+impl NoteValue {
+    /// Length in quarter notes
+    fn beats(self) -> f64 {
+        match self {
+            NoteValue::SixtyFourth => 1.0 / 16.0,
+            NoteValue::ThirtySecond => 1.0 / 8.0,
+            NoteValue::SixteenthTriplet => 1.0 / 6.0,
+            NoteValue::Sixteenth => 1.0 / 4.0,
+            NoteValue::SixteenthDotted => 3.0 / 8.0,
+            NoteValue::EighthTriplet => 1.0 / 3.0,
+            NoteValue::Eighth => 1.0 / 2.0,
+            NoteValue::EighthDotted => 3.0 / 4.0,
+            NoteValue::QuarterTriplet => 2.0 / 3.0,
+            NoteValue::Quarter => 1.0,
+            NoteValue::QuarterDotted => 3.0 / 2.0,
+            NoteValue::Half => 2.0,
+        }
+    }
+}
+
+// Number of delay lines in the tank, trading CPU for density
+#[derive(Enum, Debug, Clone, Copy, PartialEq)]
+enum Order {
+    #[name = "4 Lines"]
+    Four,
+    #[name = "8 Lines"]
+    Eight,
+    #[name = "16 Lines"]
+    Sixteen,
+    #[name = "32 Lines"]
+    ThirtyTwo,
+    #[name = "64 Lines"]
+    SixtyFour,
+}
+
+impl Order {
+    fn lines(self) -> usize {
+        match self {
+            Order::Four => 4,
+            Order::Eight => 8,
+            Order::Sixteen => 16,
+            Order::ThirtyTwo => 32,
+            Order::SixtyFour => 64,
+        }
+    }
+}
+
+// What the ducking and the gate listen to
+#[derive(Enum, Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Input,
+    Sidechain,
 }
 
 impl Default for Jverb {
     fn default() -> Self {
         let default_params = JverbParams::default();
         let mix = default_params.mix.smoothed.next();
-        let time = default_params.time.smoothed.next();
-        let lowpass = default_params.lowpass.smoothed.next();
-
-        let reverb = Reverb::new(
-            mix,
-            lowpass,
-            time,
-            (MAX_SIZE * DEFAULT_SAMPLE_RATE as f32 * get_max_float(&DELAYS)) as usize, // Max buffer size
-        );
+        let decay = default_params.decay.smoothed.next();
+        let damping = default_params.damping.smoothed.next();
+
+        let reverb = Reverb::new(mix, damping, decay);
 
         Self {
             params: Arc::new(default_params),
             audio: reverb,
+            latency: 0,
         }
     }
 }
@@ -53,6 +239,50 @@ impl Default for JverbParams {
                 .with_unit("%")
                 .with_value_to_string(formatters::v2s_f32_percentage(0))
                 .with_string_to_value(formatters::s2v_f32_percentage()),
+            // Send kills the dry signal for use on an aux return
+            mix_mode: EnumParam::new("Mix Mode", MixMode::EqualPower),
+            // Gains for the Dry/Wet mix mode
+            dry: FloatParam::new(
+                "Dry",
+                1.0,
+                FloatRange::Skewed {
+                    min: util::db_to_gain(util::MINUS_INFINITY_DB),
+                    max: util::db_to_gain(6.0),
+                    factor: FloatRange::gain_skew_factor(util::MINUS_INFINITY_DB, 6.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(1))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            wet: FloatParam::new(
+                "Wet",
+                util::db_to_gain(-6.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(util::MINUS_INFINITY_DB),
+                    max: util::db_to_gain(6.0),
+                    factor: FloatRange::gain_skew_factor(util::MINUS_INFINITY_DB, 6.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(1))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            // Delay before the wet signal
+            predelay: FloatParam::new(
+                "Pre-delay",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 500.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            // Follow the host tempo instead of the pre-delay time
+            predelay_sync: BoolParam::new("Pre-delay Sync", false),
+            predelay_note: EnumParam::new("Pre-delay Note", NoteValue::Sixteenth),
             // Reverb size
             size: FloatParam::new(
                 "Size",
@@ -65,23 +295,424 @@ impl Default for JverbParams {
             .with_smoother(SmoothingStyle::Linear(1.0))
             .with_value_to_string(formatters::v2s_f32_percentage(0))
             .with_string_to_value(formatters::s2v_f32_percentage()),
-            // Reverb time
-            time: FloatParam::new("Time", 0.9, FloatRange::Linear { min: 0.8, max: 1.0 })
+            // Time the delay lines take to glide to a new Size, longer is smoother but smears
+            // fast automation
+            size_crossfade: FloatParam::new(
+                "Size Crossfade",
+                SIZE_CROSSFADE * 1000.0,
+                FloatRange::Skewed {
+                    min: 1.0,
+                    max: MAX_SIZE_CROSSFADE * 1000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+            // Time for the tail to fall by 60 dB
+            decay: FloatParam::new(
+                "Decay",
+                2.5,
+                FloatRange::Skewed {
+                    min: 0.1,
+                    max: 30.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit(" s")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            // Hold the tail indefinitely and stop new input getting in
+            freeze: BoolParam::new("Freeze", false),
+            // Bass decay time relative to the mids
+            bass_multiplier: FloatParam::new(
+                "Bass Multiplier",
+                1.0,
+                FloatRange::Skewed {
+                    min: 0.25,
+                    max: 4.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit("x")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            // Treble decay time relative to the mids
+            treble_multiplier: FloatParam::new(
+                "Treble Multiplier",
+                1.0,
+                FloatRange::Skewed {
+                    min: 0.25,
+                    max: 4.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit("x")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            // Split between the bass and mid decay
+            low_crossover: FloatParam::new(
+                "Low Crossover",
+                250.0,
+                FloatRange::Skewed {
+                    min: 50.0,
+                    max: 1000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            // Split between the mid and treble decay
+            high_crossover: FloatParam::new(
+                "High Crossover",
+                4000.0,
+                FloatRange::Skewed {
+                    min: 1000.0,
+                    max: 16000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            // Damping lowpass cutoff inside the tank
+            damping: FloatParam::new(
+                "Damping",
+                8000.0,
+                FloatRange::Skewed {
+                    min: 200.0,
+                    max: 20000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            // Highpass inside the tank, keeps rumble from building up on long tails
+            tank_low_cut: FloatParam::new(
+                "Tank Low Cut",
+                20.0,
+                FloatRange::Skewed {
+                    min: 5.0,
+                    max: 500.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            // Input allpass diffusion
+            diffusion: FloatParam::new(
+                "Diffusion",
+                0.75,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            // How the delay lines feed back into each other
+            matrix: EnumParam::new("Matrix", Matrix::Householder),
+            // Mixing between the delay lines, parallel combs at 0%
+            tank_diffusion: FloatParam::new(
+                "Tank Diffusion",
+                1.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            // Lines in the tank. Changing it drops or adds lines without a fade, so it can click
+            // on a ringing tail
+            order: EnumParam::new("Order", Order::ThirtyTwo),
+            // Delay line lengths and random matrix, a different seed decorrelates stacked instances
+            delay_strategy: EnumParam::new("Lengths", DelayStrategy::MutuallyPrime),
+            seed: IntParam::new(
+                "Seed",
+                DEFAULT_SEED as i32,
+                IntRange::Linear { min: 1, max: 9999 },
+            ),
+            // How the stereo input is spread over the tank
+            routing: EnumParam::new("Routing", Routing::Block),
+            // Stereo width of the wet signal, mono at 0%
+            width: FloatParam::new("Width", 1.0, FloatRange::Linear { min: 0.0, max: 2.0 })
+                .with_smoother(SmoothingStyle::Linear(1.0))
+                .with_unit("%")
+                .with_value_to_string(formatters::v2s_f32_percentage(0))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
+            // Early reflections in the wet signal
+            early_level: FloatParam::new(
+                "Early Level",
+                0.5,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            // Late tail in the wet signal
+            late_level: FloatParam::new(
+                "Late Level",
+                1.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            // Early reflection room, side to side
+            room_width: FloatParam::new(
+                "Room Width",
+                12.0,
+                FloatRange::Skewed {
+                    min: 2.0,
+                    max: 50.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit(" m")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            // Early reflection room, front to back
+            room_length: FloatParam::new(
+                "Room Length",
+                18.0,
+                FloatRange::Skewed {
+                    min: 2.0,
+                    max: 50.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit(" m")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            // Early reflection room, floor to ceiling
+            room_height: FloatParam::new(
+                "Room Height",
+                6.0,
+                FloatRange::Skewed {
+                    min: 2.0,
+                    max: 20.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit(" m")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            // Source position across the room width
+            source_x: FloatParam::new("Source X", 0.5, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(1.0))
+                .with_unit("%")
+                .with_value_to_string(formatters::v2s_f32_percentage(0))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
+            // Source position along the room length
+            source_y: FloatParam::new("Source Y", 0.28, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(1.0))
+                .with_unit("%")
+                .with_value_to_string(formatters::v2s_f32_percentage(0))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
+            // Listener position across the room width
+            listener_x: FloatParam::new(
+                "Listener X",
+                0.5,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            // Listener position along the room length
+            listener_y: FloatParam::new(
+                "Listener Y",
+                0.6,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            // Energy the room's walls absorb on each reflection
+            absorption: FloatParam::new(
+                "Absorption",
+                0.3,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            // Delay line modulation speed
+            mod_rate: FloatParam::new(
+                "Mod Rate",
+                0.5,
+                FloatRange::Skewed {
+                    min: 0.05,
+                    max: 5.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            // Delay line modulation excursion
+            mod_depth: FloatParam::new(
+                "Mod Depth",
+                1.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: MAX_MOD_DEPTH * 1000.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            mod_shape: EnumParam::new("Mod Shape", LfoShape::Sine),
+            // How the tank reads between samples as the lines move
+            interpolation: EnumParam::new("Interpolation", Interpolation::Cubic),
+            // Share of the feedback that gets pitch shifted
+            shimmer: FloatParam::new("Shimmer", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
                 .with_smoother(SmoothingStyle::Linear(1.0))
+                .with_unit("%")
                 .with_value_to_string(formatters::v2s_f32_percentage(0))
                 .with_string_to_value(formatters::s2v_f32_percentage()),
-            // Lowpass cutoff
-            lowpass: FloatParam::new(
-                "Lowpass",
-                0.25,
+            shimmer_pitch: EnumParam::new("Shimmer Pitch", ShimmerInterval::OctaveUp),
+            // Output EQ on the wet signal
+            low_cut: FloatParam::new(
+                "Low Cut",
+                20.0,
+                FloatRange::Skewed {
+                    min: 20.0,
+                    max: 2000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            high_cut: FloatParam::new(
+                "High Cut",
+                20000.0,
+                FloatRange::Skewed {
+                    min: 1000.0,
+                    max: 20000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            // Positive brightens, negative darkens
+            tilt: FloatParam::new(
+                "Tilt",
+                0.0,
                 FloatRange::Linear {
-                    min: 0.001,
-                    max: 0.5,
+                    min: -6.0,
+                    max: 6.0,
                 },
             )
             .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            // Falls back to the input while the sidechain is missing, not stereo or silent since the
+            // last reset
+            key: EnumParam::new("Key", Key::Input),
+            // Wet ducking
+            duck_amount: FloatParam::new(
+                "Duck Amount",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit("%")
             .with_value_to_string(formatters::v2s_f32_percentage(0))
             .with_string_to_value(formatters::s2v_f32_percentage()),
+            duck_threshold: FloatParam::new(
+                "Duck Threshold",
+                -24.0,
+                FloatRange::Linear {
+                    min: -60.0,
+                    max: 0.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            duck_attack: FloatParam::new(
+                "Duck Attack",
+                10.0,
+                FloatRange::Skewed {
+                    min: 0.1,
+                    max: 200.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            duck_release: FloatParam::new(
+                "Duck Release",
+                250.0,
+                FloatRange::Skewed {
+                    min: 10.0,
+                    max: 5000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+            // Gated reverb, shares the key with the ducking
+            gate: BoolParam::new("Gate", false),
+            gate_threshold: FloatParam::new(
+                "Gate Threshold",
+                -30.0,
+                FloatRange::Linear {
+                    min: -60.0,
+                    max: 0.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            gate_hold: FloatParam::new(
+                "Gate Hold",
+                250.0,
+                FloatRange::Skewed {
+                    min: 10.0,
+                    max: 2000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+            gate_release: FloatParam::new(
+                "Gate Release",
+                50.0,
+                FloatRange::Skewed {
+                    min: 1.0,
+                    max: 1000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(1.0))
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+            // Plays the tail backwards, which delays the output by twice the window
+            reverse: BoolParam::new("Reverse", false),
+            // Not smoothed, every change moves the latency
+            reverse_time: FloatParam::new(
+                "Reverse Window",
+                500.0,
+                FloatRange::Skewed {
+                    min: 50.0,
+                    max: MAX_REVERSE * 1000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
         }
     }
 }
@@ -97,7 +728,11 @@ impl Plugin for Jverb {
     const DEFAULT_INPUT_CHANNELS: u32 = 2;
     const DEFAULT_OUTPUT_CHANNELS: u32 = 2;
 
-    const DEFAULT_AUX_INPUTS: Option<AuxiliaryIOConfig> = None;
+    // Stereo sidechain for the ducking and the gate
+    const DEFAULT_AUX_INPUTS: Option<AuxiliaryIOConfig> = Some(AuxiliaryIOConfig {
+        num_busses: 1,
+        num_channels: 2,
+    });
     const DEFAULT_AUX_OUTPUTS: Option<AuxiliaryIOConfig> = None;
 
     const MIDI_INPUT: MidiConfig = MidiConfig::None;
@@ -112,22 +747,28 @@ impl Plugin for Jverb {
     fn accepts_bus_config(&self, config: &BusConfig) -> bool {
         // Works with stereo
         config.num_input_channels == config.num_output_channels && config.num_input_channels == 2
-        // && config.num_input_channels <= (DELAYS.len() / 4) as u32
+        // && config.num_input_channels <= (MAX_ORDER / 4) as u32
     }
 
     fn initialize(
         &mut self,
         _bus_config: &BusConfig,
         buffer_config: &BufferConfig,
-        _context: &mut impl InitContext,
+        context: &mut impl InitContext,
     ) -> bool {
         // Resize buffers and perform other potentially expensive initialization operations here.
         // The `reset()` function is always called right after this function. You can remove this
         // function if you do not need it.
-        let sample_rate = buffer_config.sample_rate;
-
-        self.audio
-            .set_max_delays((MAX_SIZE * sample_rate * get_max_float(&DELAYS)) as usize);
+        self.audio.prepare(
+            buffer_config.sample_rate,
+            buffer_config.max_buffer_size as usize,
+        );
+        self.audio.set_reverse(
+            self.params.reverse.value(),
+            self.params.reverse_time.value() * 0.001,
+        );
+        self.latency = self.audio.latency() as u32;
+        context.set_latency_samples(self.latency);
         true
     }
 
@@ -141,23 +782,148 @@ impl Plugin for Jverb {
     fn process(
         &mut self,
         buffer: &mut Buffer,
-        _aux: &mut AuxiliaryBuffers,
+        aux: &mut AuxiliaryBuffers,
         context: &mut impl ProcessContext,
     ) -> ProcessStatus {
         let mix = self.params.mix.smoothed.next();
+        let dry = self.params.dry.smoothed.next();
+        let wet = self.params.wet.smoothed.next();
+        let predelay = self.params.predelay.smoothed.next();
         let size = self.params.size.smoothed.next();
-        let time = self.params.time.smoothed.next();
-        let lowpass = self.params.lowpass.smoothed.next();
+        let decay = self.params.decay.smoothed.next();
+        let bass_multiplier = self.params.bass_multiplier.smoothed.next();
+        let treble_multiplier = self.params.treble_multiplier.smoothed.next();
+        let low_crossover = self.params.low_crossover.smoothed.next();
+        let high_crossover = self.params.high_crossover.smoothed.next();
+        let damping = self.params.damping.smoothed.next();
+        let tank_low_cut = self.params.tank_low_cut.smoothed.next();
+        let diffusion = self.params.diffusion.smoothed.next();
+        let tank_diffusion = self.params.tank_diffusion.smoothed.next();
+        let width = self.params.width.smoothed.next();
+        let early_level = self.params.early_level.smoothed.next();
+        let late_level = self.params.late_level.smoothed.next();
+        let dimensions = [
+            self.params.room_width.smoothed.next(),
+            self.params.room_length.smoothed.next(),
+            self.params.room_height.smoothed.next(),
+        ];
+        let source = [
+            self.params.source_x.smoothed.next(),
+            self.params.source_y.smoothed.next(),
+        ];
+        let listener = [
+            self.params.listener_x.smoothed.next(),
+            self.params.listener_y.smoothed.next(),
+        ];
+        let absorption = self.params.absorption.smoothed.next();
+        let mod_rate = self.params.mod_rate.smoothed.next();
+        let mod_depth = self.params.mod_depth.smoothed.next();
+        let shimmer = self.params.shimmer.smoothed.next();
+        let low_cut = self.params.low_cut.smoothed.next();
+        let high_cut = self.params.high_cut.smoothed.next();
+        let tilt = self.params.tilt.smoothed.next();
+        let duck_amount = self.params.duck_amount.smoothed.next();
+        let duck_threshold = self.params.duck_threshold.smoothed.next();
+        let duck_attack = self.params.duck_attack.smoothed.next();
+        let duck_release = self.params.duck_release.smoothed.next();
+        let gate_threshold = self.params.gate_threshold.smoothed.next();
+        let gate_hold = self.params.gate_hold.smoothed.next();
+        let gate_release = self.params.gate_release.smoothed.next();
 
-        let sample_rate = context.transport().sample_rate;
+        let predelay = if self.params.predelay_sync.value() {
+            let tempo = context.transport().tempo.unwrap_or(FALLBACK_TEMPO);
+            (self.params.predelay_note.value().beats() * 60.0 / tempo) as f32
+        } else {
+            predelay * 0.001
+        };
 
         self.audio.set_mix(mix);
-        self.audio.set_gain(time);
+        self.audio.set_mix_mode(self.params.mix_mode.value());
+        self.audio.set_dry_wet(dry, wet);
+        self.audio.set_predelay(predelay);
+        self.audio.set_lengths(
+            self.params.seed.value() as u32,
+            self.params.delay_strategy.value(),
+        );
+        self.audio
+            .set_crossfade(self.params.size_crossfade.value() * 0.001);
+        self.audio.set_size(size);
+        self.audio.set_decay(decay);
+        self.audio.set_freeze(self.params.freeze.value());
         self.audio
-            .set_delays(DELAYS.map(|delay| (delay * size * sample_rate as f32) as usize));
-        self.audio.set_cutoff(lowpass);
+            .set_decay_multipliers(bass_multiplier, treble_multiplier);
+        self.audio.set_crossovers(low_crossover, high_crossover);
+        self.audio.set_cutoff(damping);
+        self.audio.set_tank_low_cut(tank_low_cut);
+        self.audio.set_diffusion(diffusion);
+        self.audio.set_matrix(self.params.matrix.value());
+        self.audio.set_tank_diffusion(tank_diffusion);
+        self.audio.set_order(self.params.order.value().lines());
+        self.audio.set_routing(self.params.routing.value());
+        self.audio.set_width(width);
+        self.audio.set_levels(early_level, late_level);
+        self.audio.set_room(Room {
+            dimensions,
+            source: [
+                source[0] * dimensions[0],
+                source[1] * dimensions[1],
+                EAR_HEIGHT,
+            ],
+            listener: [
+                listener[0] * dimensions[0],
+                listener[1] * dimensions[1],
+                EAR_HEIGHT,
+            ],
+            absorption,
+        });
+        self.audio.set_modulation(mod_rate, mod_depth * 0.001);
+        self.audio
+            .set_modulation_shape(self.params.mod_shape.value());
+        self.audio
+            .set_interpolation(self.params.interpolation.value());
+        self.audio
+            .set_shimmer(shimmer, self.params.shimmer_pitch.value());
+        self.audio.set_eq(low_cut, high_cut, tilt);
+        self.audio.set_ducking(
+            duck_amount,
+            duck_threshold,
+            duck_attack * 0.001,
+            duck_release * 0.001,
+        );
+        self.audio.set_gate(
+            self.params.gate.value(),
+            gate_threshold,
+            gate_hold * 0.001,
+            gate_release * 0.001,
+        );
+        self.audio.set_reverse(
+            self.params.reverse.value(),
+            self.params.reverse_time.value() * 0.001,
+        );
+
+        let samples = buffer.samples();
+        let sidechain = match self.params.key.value() {
+            Key::Sidechain => aux
+                .inputs
+                .first()
+                .map(|sidechain| sidechain.as_slice_immutable())
+                .filter(|sidechain| sidechain.len() == 2 && sidechain[0].len() == samples),
+            Key::Input => None,
+        };
 
-        self.audio.process_buffer_slice(buffer.as_slice());
+        match sidechain {
+            Some(sidechain) => self
+                .audio
+                .process_buffer_slice_keyed(buffer.as_slice(), sidechain),
+            None => self.audio.process_buffer_slice(buffer.as_slice()),
+        }
+
+        // A new reverse window takes over partway through a block, report it once it has
+        let latency = self.audio.latency() as u32;
+        if latency != self.latency {
+            self.latency = latency;
+            context.set_latency_samples(latency);
+        }
 
         ProcessStatus::Normal
     }